@@ -0,0 +1,1201 @@
+use api::price::{PriceFeed, PriceProvider, SUPPORTED_CURRENCIES};
+use api::response::{Card, Message, Section};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use config::Config;
+use custody;
+use db::ledger::{self, Posting};
+use db::{self, KeyCipher};
+use futures::Future;
+use node::{self, Amount, AmountInput, NanoAddress, NanoRpcClient, Signer};
+use rand::{thread_rng, Rng};
+use regex::Regex;
+use rusqlite::{self, Connection};
+use std::error::Error;
+use std::sync::Mutex;
+
+lazy_static! {
+    // Dot separated local part (plus-addressing included) at one or more domain labels
+    static ref EMAIL_REGEX: Regex = Regex::new(
+        r"(?i)^[a-z0-9_%+-]+(\.[a-z0-9_%+-]+)*@([a-z0-9]([a-z0-9-]*[a-z0-9])?\.)+[a-z]{2,}$"
+    ).expect("valid email regex");
+}
+
+const LINK_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+const LINK_CODE_MINUTES: i64 = 10;
+const MAX_TIP_RECEIVERS: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Balance,
+    Deposit,
+    Tip {
+        receivers: Vec<String>,
+        amount: AmountInput,
+        share: Share,
+    },
+    Withdraw { address: NanoAddress, amount: AmountInput },
+    History { count: usize },
+    Link { code: Option<String> },
+    Currency { code: Option<String> },
+    Unknown,
+}
+
+/// How a tip amount is divided between several receivers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Share {
+    Each,
+    Split,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Hangouts,
+    Teams,
+}
+
+impl Platform {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Platform::Hangouts => "hangouts",
+            Platform::Teams => "teams",
+        }
+    }
+}
+
+pub struct Context<'a> {
+    pub config: &'a Config,
+    pub db_conn: &'a Mutex<Connection>,
+    pub node: &'a NanoRpcClient,
+    pub cipher: &'a KeyCipher,
+    pub price: &'a PriceFeed,
+}
+
+pub struct User {
+    pub platform: Platform,
+    pub id: String,
+    pub display_name: String,
+}
+
+/// `mentions` are the users @mentioned in `text`, whose mention the platform has already
+/// replaced with their user id. `direct` tells a direct message with the bot from a room.
+pub fn handle(ctx: &Context, text: &str, user: &User, mentions: &[User], direct: bool) -> Message {
+    match parse(text) {
+        Ok(command) => execute(ctx, command, user, mentions, direct),
+        Err(e) => Message::text(e),
+    }
+}
+
+pub fn parse(text: &str) -> Result<Command, String> {
+    let text = text.trim();
+
+    match text.split_whitespace().next() {
+        Some("!help") => Ok(Command::Help),
+        Some("!balance") => Ok(Command::Balance),
+        Some("!deposit") => Ok(Command::Deposit),
+        Some("!tip") => {
+            let (receivers, amount, share) = parse_tip_arguments(text)?;
+
+            Ok(Command::Tip {
+                receivers: receivers.iter().map(|r| r.to_string()).collect(),
+                amount,
+                share,
+            })
+        }
+        Some("!withdraw") => {
+            let (address, amount) = parse_withdraw_arguments(text)?;
+
+            Ok(Command::Withdraw { address, amount })
+        }
+        Some("!history") => Ok(Command::History {
+            count: parse_history_arguments(text)?,
+        }),
+        Some("!link") => Ok(Command::Link {
+            code: text.split_whitespace().nth(1).map(|c| c.to_uppercase()),
+        }),
+        Some("!currency") => Ok(Command::Currency {
+            code: text.split_whitespace().nth(1).map(|c| c.to_uppercase()),
+        }),
+        _ => Ok(Command::Unknown),
+    }
+}
+
+pub fn execute(
+    ctx: &Context,
+    command: Command,
+    user: &User,
+    mentions: &[User],
+    direct: bool,
+) -> Message {
+    match command {
+        Command::Help => Message::text(
+            "Available commands: `!balance` `!deposit` `!tip @someone... amount [each|split]` `!withdraw wallet_address amount` `!history [count]` `!link [code]` `!currency [code]`. Amounts are in NANO unless given as `500 knano`, `3 raw` or `€2`"
+                .to_string(),
+        ),
+        Command::Balance => get_balance(ctx, user),
+        Command::Deposit => get_deposit(ctx, user),
+        Command::Tip {
+            receivers,
+            amount,
+            share,
+        } => try_tip(ctx, user, &receivers, &amount, share, mentions),
+        Command::Withdraw { address, amount } => {
+            try_withdraw(ctx, user, &address, &amount)
+        }
+        Command::History { count } => get_history(ctx, user, count),
+        Command::Link { code: None } => start_link(ctx, user, direct),
+        Command::Link { code: Some(code) } => confirm_link(ctx, user, &code),
+        Command::Currency { code } => set_currency(ctx, user, code),
+        Command::Unknown => Message::text(format!(
+            "Did not quite catch that, *{}*, type `!help` for help",
+            user.display_name
+        )),
+    }
+}
+
+fn get_deposit(ctx: &Context, user: &User) -> Message {
+    let acc: node::Account = match try_get_account(ctx, user.platform, &user.id) {
+        Ok(a) => a,
+        Err(e) => return Message::text(format!("There was an error fetching the account: {}", e)),
+    };
+
+    let mut details = Section::new()
+        .key_value("To", user.id.to_owned())
+        .key_value("Wallet", acc.account.to_owned());
+
+    // The deposit address is still useful when the node cannot report a balance
+    if let Ok(bal) = get_funds(ctx, &acc) {
+        let currency: String = display_currency(ctx, acc.id);
+
+        details = details.key_value(
+            "Balance",
+            with_fiat(bal.balance, &currency, get_price(ctx, &currency)),
+        );
+    }
+
+    Message::card(
+        Card::new("Deposit").section(details).section(
+            Section::with_header("Scan QR Code using Nano mobile wallet").qr_code(acc.account),
+        ),
+    )
+}
+
+fn try_tip(
+    ctx: &Context,
+    user: &User,
+    receivers: &[String],
+    amount: &AmountInput,
+    share: Share,
+    mentions: &[User],
+) -> Message {
+    for (i, receiver) in receivers.iter().enumerate() {
+        if mentions.iter().any(|m| &m.id == receiver) {
+            continue;
+        }
+
+        if let Err(e) = validate_receiver(user.platform, receiver) {
+            return Message::text(format!("Receiver {} `{}` {}", i + 1, receiver, e));
+        }
+    }
+
+    let amount: Amount = match resolve_amount(ctx, amount) {
+        Ok(a) => a,
+        Err(e) => return Message::text(e),
+    };
+
+    let count = receivers.len() as u128;
+    let each: Amount = match share {
+        Share::Each => amount,
+        Share::Split => Amount::from_raw(amount.raw() / count),
+    };
+
+    if each.is_zero() {
+        return Message::text("The amount is too small to split between everyone".to_string());
+    }
+
+    let total: Amount = match each.raw().checked_mul(count) {
+        Some(t) => Amount::from_raw(t),
+        None => return Message::text("Amount is too large".to_string()),
+    };
+
+    let sender_acc: node::Account =
+        match try_get_account(ctx, user.platform, &user.id) {
+            Ok(a) => a,
+            Err(e) => {
+                return Message::text(format!(
+                    "There was an error fetching the sender account: {}",
+                    e
+                ))
+            }
+        };
+
+    let available: Amount = match spendable(ctx, &sender_acc) {
+        Ok(a) => a,
+        Err(e) => return Message::text(e),
+    };
+
+    if total > available {
+        return Message::text(format!(
+            "Insufficient balance, these tips need {} but you have {}",
+            total, available
+        ));
+    }
+
+    let currency: String = display_currency(ctx, sender_acc.id);
+    let price: Option<f64> = get_price(ctx, &currency);
+
+    let reference: &str = if custodial(ctx) { "Ledger entry" } else { "Block" };
+    let mut sent: usize = 0;
+    let mut results: Vec<Section> = Vec::new();
+
+    for receiver in receivers {
+        let name: String = match mentions.iter().find(|m| &m.id == receiver) {
+            Some(m) => m.display_name.to_owned(),
+            None => receiver.to_owned(),
+        };
+
+        match send_tip(ctx, user, &sender_acc, receiver, each) {
+            Ok((account, block)) => {
+                sent += 1;
+                results.push(
+                    Section::new()
+                        .key_value("To", name)
+                        .key_value("Wallet", account)
+                        .key_value(reference, block),
+                );
+            }
+            Err(e) => results.push(
+                Section::new()
+                    .key_value("To", name)
+                    .key_value("Failed", e),
+            ),
+        }
+    }
+
+    let header: &str = match (sent, receivers.len()) {
+        (0, 1) => "Tip failed",
+        (0, _) => "Tips failed",
+        (_, 1) => "Tip sent!",
+        (s, n) if s == n => "Tips sent!",
+        _ => "Some tips failed",
+    };
+
+    let mut summary = Section::new()
+        .key_value("From", user.display_name.to_owned())
+        .key_value("Amount", with_fiat(each, &currency, price));
+
+    if receivers.len() > 1 {
+        let sent_total = Amount::from_raw(each.raw() * sent as u128);
+
+        summary = summary.key_value(
+            "Sent",
+            format!(
+                "{} of {} ({})",
+                sent,
+                receivers.len(),
+                with_fiat(sent_total, &currency, price)
+            ),
+        );
+    }
+
+    Message::card(
+        results
+            .into_iter()
+            .fold(Card::new(header).section(summary), |card, section| {
+                card.section(section)
+            }),
+    )
+}
+
+/// Sends one tip and records it, returning the receiver wallet and the block hash, or the ledger
+/// entry with custody.
+fn send_tip(
+    ctx: &Context,
+    user: &User,
+    sender_acc: &node::Account,
+    receiver: &str,
+    amount: Amount,
+) -> Result<(String, String), String> {
+    let receiver_acc: node::Account = match try_get_account(ctx, user.platform, receiver) {
+        Ok(a) => a,
+        Err(e) => return Err(format!("could not fetch the receiver account: {}", e)),
+    };
+
+    // Linked identities share an account, so this also catches tips to your other platform
+    if receiver_acc.id == sender_acc.id {
+        return Err("you cannot tip yourself".to_string());
+    }
+
+    if custodial(ctx) {
+        let entry: i64 = match ledger::post(
+            ctx.db_conn,
+            &Posting {
+                debit: &ledger::user_account(sender_acc.id),
+                credit: &ledger::user_account(receiver_acc.id),
+                amount,
+                kind: ledger::TIP,
+                reference: receiver,
+            },
+        ) {
+            Ok(e) => e,
+            Err(ledger::LedgerError::InsufficientFunds { .. }) => {
+                return Err("insufficient balance".to_string())
+            }
+            Err(e) => {
+                println!("Could not book a tip to {}: {}", receiver, e);
+                return Err("the ledger could not be updated".to_string());
+            }
+        };
+
+        record_transaction(
+            ctx,
+            user,
+            &sender_acc.account,
+            receiver,
+            &receiver_acc.account,
+            amount,
+            &format!("ledger:{}", entry),
+            "tip",
+        );
+
+        return Ok((receiver_acc.account, entry.to_string()));
+    }
+
+    let receiver_address: NanoAddress = match NanoAddress::parse(&receiver_acc.account) {
+        Ok(a) => a,
+        Err(e) => return Err(format!("the receiver account is not valid: {}", e)),
+    };
+
+    let block: String = match signer(ctx).send(sender_acc, &receiver_address, amount) {
+        Ok(b) => b,
+        Err(e) => return Err(e.user_message().to_string()),
+    };
+
+    record_transaction(
+        ctx,
+        user,
+        &sender_acc.account,
+        receiver,
+        &receiver_acc.account,
+        amount,
+        &block,
+        "tip",
+    );
+
+    Ok((receiver_acc.account, block))
+}
+
+fn try_withdraw(
+    ctx: &Context,
+    user: &User,
+    address: &NanoAddress,
+    amount: &AmountInput,
+) -> Message {
+    let amount: Amount = match resolve_amount(ctx, amount) {
+        Ok(a) => a,
+        Err(e) => return Message::text(e),
+    };
+
+    let sender_acc: node::Account =
+        match try_get_account(ctx, user.platform, &user.id) {
+            Ok(a) => a,
+            Err(e) => {
+                return Message::text(format!(
+                    "There was an error fetching the sender account: {}",
+                    e
+                ))
+            }
+        };
+
+    let available: Amount = match spendable(ctx, &sender_acc) {
+        Ok(a) => a,
+        Err(e) => return Message::text(e),
+    };
+
+    if amount > available {
+        return Message::text("Insufficient balance for this withdrawal".to_string());
+    }
+
+    // With custody every withdrawal is paid from the hot wallet
+    let sent: Result<String, String> = if custodial(ctx) {
+        custody::withdraw(ctx.db_conn, &signer(ctx), &sender_acc, address, amount)
+            .map_err(|e| e.user_message().to_string())
+    } else {
+        signer(ctx)
+            .send(&sender_acc, address, amount)
+            .map_err(|e| e.user_message().to_string())
+    };
+
+    match sent {
+        Ok(block) => {
+            let currency: String = display_currency(ctx, sender_acc.id);
+            let price: Option<f64> = get_price(ctx, &currency);
+            let address: String = address.to_string();
+
+            record_transaction(
+                ctx,
+                user,
+                &sender_acc.account,
+                &address,
+                &address,
+                amount,
+                &block,
+                "withdrawal",
+            );
+
+            Message::card(
+                Card::new("Withdrawal sent!").section(
+                    Section::new()
+                        .key_value("From", sender_acc.account)
+                        .key_value("To", address)
+                        .key_value("Amount", with_fiat(amount, &currency, price))
+                        .key_value("Block", block.to_owned())
+                        .button(
+                            "View block",
+                            format!("https://nanocrawler.cc/explorer/block/{}", block),
+                        ),
+                ),
+            )
+        }
+        Err(e) => Message::text(format!("Could not send the withdrawal: {}", e)),
+    }
+}
+
+fn record_transaction(
+    ctx: &Context,
+    user: &User,
+    sender_account: &str,
+    receiver: &str,
+    receiver_account: &str,
+    amount: Amount,
+    block: &str,
+    kind: &str,
+) {
+    let transaction = db::Transaction {
+        sender: user.id.to_owned(),
+        sender_account: sender_account.to_owned(),
+        receiver: receiver.to_owned(),
+        receiver_account: receiver_account.to_owned(),
+        amount: amount.raw().to_string(),
+        block: block.to_owned(),
+        platform: user.platform.as_str().to_string(),
+        kind: kind.to_string(),
+        timestamp: Utc::now().to_rfc3339(),
+    };
+
+    if let Err(e) = db::add_transaction(ctx.db_conn, &transaction) {
+        println!("Could not record {} {}: {}", kind, block, e);
+    }
+}
+
+fn get_balance(ctx: &Context, user: &User) -> Message {
+    let acc: node::Account = match try_get_account(ctx, user.platform, &user.id) {
+        Ok(a) => a,
+        Err(e) => {
+            return Message::text(format!("An error has occured fetching the account: {}", e))
+        }
+    };
+
+    let bal: node::Balance = match get_funds(ctx, &acc) {
+        Ok(b) => b,
+        Err(e) => return Message::text(e),
+    };
+
+    let currency: String = display_currency(ctx, acc.id);
+    let price: Option<f64> = get_price(ctx, &currency);
+
+    Message::card(
+        Card::new("Balance").section(
+            Section::new()
+                .key_value("Current", with_fiat(bal.balance, &currency, price))
+                .key_value("Pending", with_fiat(bal.pending, &currency, price)),
+        ),
+    )
+}
+
+fn get_history(ctx: &Context, user: &User, count: usize) -> Message {
+    let acc: node::Account = match try_get_account(ctx, user.platform, &user.id) {
+        Ok(a) => a,
+        Err(e) => {
+            return Message::text(format!("An error has occured fetching the account: {}", e))
+        }
+    };
+
+    let transactions: Vec<db::Transaction> = match db::get_transactions(
+        ctx.db_conn,
+        &acc.account,
+        user.platform.as_str(),
+        &user.id,
+        count as i64,
+    ) {
+        Ok(t) => t,
+        Err(_) => return Message::text("An error has occured fetching the history".to_string()),
+    };
+
+    let entries: Vec<node::HistoryEntry> =
+        match ctx.node.get_account_history(&acc.account, count as u32).wait() {
+            Ok(h) => h,
+            Err(node::RpcError::Node(node::NodeError::AccountNotFound)) => vec![],
+            Err(e) => {
+                return Message::text(format!(
+                    "Could not fetch the history: {}",
+                    e.user_message()
+                ))
+            }
+        };
+
+    let mut items: Vec<(DateTime<Utc>, String, Amount)> = transactions
+        .into_iter()
+        .map(|t| {
+            let description = if t.sender_account == acc.account || t.sender == user.id {
+                format!("{} to {}", t.kind, t.receiver)
+            } else {
+                format!("{} from {}", t.kind, t.sender)
+            };
+            let timestamp = DateTime::parse_from_rfc3339(&t.timestamp)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc.timestamp(0, 0));
+
+            // Amounts were recorded in raw
+            let amount = Amount::from_raw_str(&t.amount).unwrap_or_default();
+
+            (timestamp, description, amount)
+        })
+        .collect();
+
+    for entry in entries {
+        if entry.entry_type != "receive" {
+            continue;
+        }
+
+        match db::is_bot_account(ctx.db_conn, &entry.account) {
+            Ok(false) => (),
+            _ => continue,
+        }
+
+        let timestamp = Utc.timestamp(entry.local_timestamp.parse().unwrap_or(0), 0);
+
+        items.push((
+            timestamp,
+            format!("deposit from {}", entry.account),
+            entry.amount,
+        ));
+    }
+
+    if items.is_empty() {
+        return Message::text("No transactions yet".to_string());
+    }
+
+    items.sort_by(|a, b| b.0.cmp(&a.0));
+    items.truncate(count);
+
+    let mut section = Section::new();
+
+    for (timestamp, description, amount) in items {
+        section = section.key_value(
+            format!("{} {}", timestamp.format("%Y-%m-%d %H:%M"), description),
+            amount.to_string(),
+        );
+    }
+
+    Message::card(Card::new("History").section(section))
+}
+
+/// Link codes are only handed out in direct messages, anyone in a room could confirm a code
+/// posted there and take over the wallet.
+fn start_link(ctx: &Context, user: &User, direct: bool) -> Message {
+    if !direct {
+        return Message::text(
+            "Link codes are only sent in private, message me `!link` directly".to_string(),
+        );
+    }
+
+    let acc: node::Account = match try_get_account(ctx, user.platform, &user.id) {
+        Ok(a) => a,
+        Err(e) => {
+            return Message::text(format!("An error has occured fetching the account: {}", e))
+        }
+    };
+
+    let code: String = generate_link_code();
+    let expires_at: i64 = (Utc::now() + Duration::minutes(LINK_CODE_MINUTES)).timestamp();
+
+    match db::add_link_code(ctx.db_conn, &code, acc.id, user.platform.as_str(), expires_at) {
+        Ok(_) => Message::text(format!(
+            "Your link code is `{}`. Send `!link {}` to me on the other platform within {} minutes to share this wallet there",
+            code, code, LINK_CODE_MINUTES
+        )),
+        Err(_) => Message::text("An error has occured creating the link code".to_string()),
+    }
+}
+
+/// The code is only consumed once every check has passed, so a confirmation from the wrong
+/// platform or a funded wallet leaves it usable.
+fn confirm_link(ctx: &Context, user: &User, code: &str) -> Message {
+    let link_code: db::LinkCode = match db::get_link_code(ctx.db_conn, code) {
+        Ok(l) => l,
+        Err(_) => return Message::text("Unknown link code".to_string()),
+    };
+
+    if link_code.expires_at < Utc::now().timestamp() {
+        return Message::text(
+            "This link code has expired, type `!link` to get a new one".to_string(),
+        );
+    }
+
+    if link_code.platform == user.platform.as_str() {
+        return Message::text(
+            "Link codes have to be confirmed from the other platform".to_string(),
+        );
+    }
+
+    let existing: Option<node::Account> =
+        match db::get_account(ctx.db_conn, user.platform.as_str(), &user.id) {
+            Ok(a) => Some(a),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(_) => {
+                return Message::text(
+                    "An error has occured fetching the account on this platform".to_string(),
+                )
+            }
+        };
+
+    if let Some(existing) = existing {
+        if existing.id == link_code.account_id {
+            return Message::text("These identities are already linked".to_string());
+        }
+
+        // The wallet being replaced must be empty, otherwise its funds would become unreachable
+        match get_funds(ctx, &existing) {
+            Ok(ref b) if b.balance.is_zero() && b.pending.is_zero() => (),
+            Ok(_) => {
+                return Message::text(
+                    "Your wallet on this platform still holds funds, withdraw or tip them before linking"
+                        .to_string(),
+                )
+            }
+            Err(e) => return Message::text(e),
+        }
+    }
+
+    match db::take_link_code(ctx.db_conn, code) {
+        Ok(true) => (),
+        Ok(false) => return Message::text("Unknown link code".to_string()),
+        Err(_) => return Message::text("An error has occured linking the accounts".to_string()),
+    }
+
+    match db::link_identity(ctx.db_conn, link_code.account_id, user.platform.as_str(), &user.id) {
+        Ok(_) => Message::text(format!(
+            "Linked! *{}* now shares one wallet across {} and {}",
+            user.display_name,
+            link_code.platform,
+            user.platform.as_str()
+        )),
+        Err(_) => Message::text("An error has occured linking the accounts".to_string()),
+    }
+}
+
+fn set_currency(ctx: &Context, user: &User, code: Option<String>) -> Message {
+    let acc: node::Account = match try_get_account(ctx, user.platform, &user.id) {
+        Ok(a) => a,
+        Err(e) => {
+            return Message::text(format!("An error has occured fetching the account: {}", e))
+        }
+    };
+
+    let code: String = match code {
+        Some(c) => c,
+        None => {
+            return Message::text(format!(
+                "Amounts are shown in {}. Type `!currency code` to change it, one of: {}",
+                display_currency(ctx, acc.id),
+                SUPPORTED_CURRENCIES.join(", ")
+            ))
+        }
+    };
+
+    if !SUPPORTED_CURRENCIES.contains(&code.as_str()) {
+        return Message::text(format!(
+            "{} is not supported, pick one of: {}",
+            code,
+            SUPPORTED_CURRENCIES.join(", ")
+        ));
+    }
+
+    match db::set_currency(ctx.db_conn, acc.id, &code) {
+        Ok(_) => Message::text(format!("Amounts will now be shown in {}", code)),
+        Err(_) => Message::text("An error has occured saving the currency".to_string()),
+    }
+}
+
+fn custodial(ctx: &Context) -> bool {
+    ctx.config.custody.enabled
+}
+
+/// What `acc` may tip or withdraw. With custody that is its ledger balance, which needs no node.
+fn spendable(ctx: &Context, acc: &node::Account) -> Result<Amount, String> {
+    if !custodial(ctx) {
+        return match ctx.node.get_balance(&acc.account).wait() {
+            Ok(b) => Ok(b.balance),
+            Err(e) => Err(format!("Could not fetch the balance: {}", e.user_message())),
+        };
+    }
+
+    match ledger::balance(ctx.db_conn, &ledger::user_account(acc.id)) {
+        Ok(b) => Ok(b),
+        Err(e) => {
+            println!("Could not read the ledger balance of {}: {}", acc.account, e);
+            Err("Could not fetch the balance from the ledger".to_string())
+        }
+    }
+}
+
+/// Balance and pending funds of `acc`. With custody, deposits stay pending until they are swept
+/// into the hot wallet and credited.
+fn get_funds(ctx: &Context, acc: &node::Account) -> Result<node::Balance, String> {
+    let chain: node::Balance = match ctx.node.get_balance(&acc.account).wait() {
+        Ok(b) => b,
+        Err(e) => return Err(format!("Could not fetch the balance: {}", e.user_message())),
+    };
+
+    if !custodial(ctx) {
+        return Ok(chain);
+    }
+
+    Ok(node::Balance {
+        balance: spendable(ctx, acc)?,
+        pending: chain.balance.checked_add(chain.pending).unwrap_or_default(),
+    })
+}
+
+fn display_currency(ctx: &Context, account_id: i64) -> String {
+    match db::get_currency(ctx.db_conn, account_id) {
+        Ok(Some(c)) => c,
+        _ => ctx.config.price.default_currency.to_owned(),
+    }
+}
+
+/// Price of one NANO, or `None` when the price feed is unavailable.
+fn get_price(ctx: &Context, currency: &str) -> Option<f64> {
+    ctx.price.quote(currency).ok().map(|q| q.price)
+}
+
+fn with_fiat(amount: Amount, currency: &str, price: Option<f64>) -> String {
+    match price {
+        Some(p) => format!("{} ({})", amount, format_fiat(amount.to_fiat(p), currency)),
+        None => amount.to_string(),
+    }
+}
+
+fn format_fiat(value: f64, currency: &str) -> String {
+    match currency {
+        "EUR" => format!("€{:.2}", value),
+        "USD" => format!("${:.2}", value),
+        "GBP" => format!("£{:.2}", value),
+        "JPY" => format!("¥{:.0}", value),
+        _ => format!("{:.2} {}", value, currency),
+    }
+}
+
+fn generate_link_code() -> String {
+    let mut rng = thread_rng();
+
+    (0..LINK_CODE_LENGTH)
+        .map(|_| LINK_CODE_ALPHABET[rng.gen_range(0, LINK_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Reads `!tip @someone... amount [each|split]`. The receivers end where the rest of the
+/// arguments first parse as an amount, which lets the amount carry a unit.
+fn parse_tip_arguments(text_args: &str) -> Result<(Vec<&str>, AmountInput, Share), String> {
+    let mut args: Vec<&str> = text_args.split_whitespace().skip(1).collect();
+
+    let last: Option<String> = args.last().map(|a| a.to_lowercase());
+    let share: Share = match last {
+        Some(ref a) if a == "split" => Share::Split,
+        _ => Share::Each,
+    };
+
+    if last.map_or(false, |a| a == "split" || a == "each") {
+        args.pop();
+    }
+
+    if args.is_empty() {
+        return Err("No receiver supplied".to_string());
+    }
+
+    let mut error: String = "No amount supplied".to_string();
+
+    for split in 1..args.len() {
+        match AmountInput::parse(&args[split..].join(" ")) {
+            Ok(amount) => {
+                let mut receivers: Vec<&str> = Vec::new();
+
+                for receiver in &args[..split] {
+                    if !receivers.contains(receiver) {
+                        receivers.push(receiver);
+                    }
+                }
+
+                if receivers.len() > MAX_TIP_RECEIVERS {
+                    return Err(format!(
+                        "At most {} receivers can be tipped at once",
+                        MAX_TIP_RECEIVERS
+                    ));
+                }
+
+                return Ok((receivers, amount, share));
+            }
+            Err(e) => error = format!("Argument {}: {}", split + 1, e),
+        }
+    }
+
+    Err(error)
+}
+
+fn parse_withdraw_arguments(text_args: &str) -> Result<(NanoAddress, AmountInput), String> {
+    let mut args = text_args.split_whitespace();
+
+    let address: NanoAddress = match args.nth(1) {
+        Some(address) => match NanoAddress::parse(address) {
+            Ok(a) => a,
+            Err(e) => return Err(format!("Could not parse wallet address: {}", e)),
+        },
+        _ => return Err("No wallet address supplied".to_string()),
+    };
+
+    Ok((address, parse_amount(args)?))
+}
+
+/// The amount is whatever follows the other arguments, so units may be separated by a space.
+fn parse_amount<'a, I: Iterator<Item = &'a str>>(args: I) -> Result<AmountInput, String> {
+    let text: String = args.collect::<Vec<&str>>().join(" ");
+
+    AmountInput::parse(&text).map_err(|e| e.to_string())
+}
+
+fn resolve_amount(ctx: &Context, amount: &AmountInput) -> Result<Amount, String> {
+    match *amount {
+        AmountInput::Nano(a) => Ok(a),
+        AmountInput::Fiat {
+            ref currency,
+            value,
+        } if SUPPORTED_CURRENCIES.contains(&currency.as_str()) =>
+        {
+            match get_price(ctx, currency) {
+                Some(price) => Amount::from_fiat(value, price).map_err(|e| e.to_string()),
+                None => Err("Could not fetch the NANO price, try again later".to_string()),
+            }
+        }
+        AmountInput::Fiat { ref currency, .. } => {
+            Err(format!("Amounts in {} are not supported", currency))
+        }
+    }
+}
+
+fn parse_history_arguments(text_args: &str) -> Result<usize, String> {
+    match text_args.split_whitespace().nth(1) {
+        Some(count) => match count.parse::<usize>() {
+            Ok(c) if c > 0 && c <= 20 => Ok(c),
+            _ => Err("History count must be a number between 1 and 20".to_string()),
+        },
+        None => Ok(5),
+    }
+}
+
+/// Checks a receiver that was typed rather than mentioned. Teams users are only known by the id
+/// a mention resolves to, so a typed name would open an account nobody can ever reach.
+fn validate_receiver(platform: Platform, receiver: &str) -> Result<(), String> {
+    match platform {
+        Platform::Hangouts => {
+            if validate_email_address(receiver) {
+                Ok(())
+            } else {
+                Err("is not an email address".to_string())
+            }
+        }
+        Platform::Teams => Err("is not a mention, @mention the people you want to tip".to_string()),
+    }
+}
+
+fn try_get_account(
+    ctx: &Context,
+    platform: Platform,
+    user_id: &str,
+) -> Result<node::Account, String> {
+    if let Ok(a) = get_account(ctx, platform, user_id) {
+        return Ok(a);
+    }
+
+    try_create_account(ctx, platform, user_id)?;
+
+    match get_account(ctx, platform, user_id) {
+        Ok(a) => Ok(a),
+        Err(_) => Err("the new account could not be loaded".to_string()),
+    }
+}
+
+fn get_account(
+    ctx: &Context,
+    platform: Platform,
+    user_id: &str,
+) -> Result<node::Account, Box<Error>> {
+    Ok(db::get_account(ctx.db_conn, platform.as_str(), user_id)?)
+}
+
+fn try_create_account(ctx: &Context, platform: Platform, user_id: &str) -> Result<(), String> {
+    let (key, wallet): (node::Key, String) = match signer(ctx).create_key() {
+        Ok(k) => k,
+        Err(e) => return Err(format!("could not create a key, {}", e.user_message())),
+    };
+
+    let sealed_key = node::Key {
+        private: match ctx.cipher.encrypt(&key.account, &key.private) {
+            Ok(p) => p,
+            Err(e) => return Err(e.to_string()),
+        },
+        ..key
+    };
+
+    match db::add_account(ctx.db_conn, &sealed_key, &wallet, platform.as_str(), user_id) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("could not store the new account".to_string()),
+    }
+}
+
+fn signer<'a>(ctx: &Context<'a>) -> Signer<'a> {
+    Signer::new(ctx.node, ctx.cipher, &ctx.config.node)
+}
+
+fn validate_email_address(email: &str) -> bool {
+    EMAIL_REGEX.is_match(email)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::RpcConfig;
+    use std::time::Duration;
+
+    const ALICE: &str = "alice@example.com";
+    const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn user(platform: Platform, id: &str) -> User {
+        User {
+            platform,
+            id: id.to_string(),
+            display_name: id.to_string(),
+        }
+    }
+
+    /// Adds an account for `id` on Hangouts holding one NANO in the ledger, linked to `teams_id`.
+    fn funded_account(db_conn: &Mutex<Connection>, id: &str, teams_id: &str) -> node::Account {
+        let key = node::Key {
+            account: format!("nano_{}", id),
+            public: "AB".to_string(),
+            private: "CD".to_string(),
+        };
+        let account_id: i64 = db::add_account(db_conn, &key, "wallet", "hangouts", id).unwrap();
+
+        db::link_identity(db_conn, account_id, "teams", teams_id).unwrap();
+        ledger::post(
+            db_conn,
+            &Posting {
+                debit: ledger::EXTERNAL,
+                credit: &ledger::user_account(account_id),
+                amount: Amount::from_nano_str("1").unwrap(),
+                kind: ledger::DEPOSIT,
+                reference: id,
+            },
+        ).unwrap();
+
+        db::get_account(db_conn, "hangouts", id).unwrap()
+    }
+
+    fn unreachable_node() -> NanoRpcClient {
+        NanoRpcClient::new(RpcConfig {
+            url: "http://127.0.0.1:1".to_string(),
+            timeout: Duration::from_secs(1),
+            keep_alive_timeout: Duration::from_secs(1),
+        }).unwrap()
+    }
+
+    #[test]
+    fn refuses_tips_to_yourself() {
+        let db_conn = db::get_connection(":memory:").unwrap();
+        let node: NanoRpcClient = unreachable_node();
+        let cipher = KeyCipher::from_hex(MASTER_KEY).unwrap();
+        let price = PriceFeed::new(Vec::new(), Duration::from_secs(0));
+        let alice: node::Account = funded_account(&db_conn, ALICE, "29:alice");
+        let one: Amount = Amount::from_nano_str("0.1").unwrap();
+
+        for &enabled in &[true, false] {
+            let mut config = Config::default();
+            config.custody.enabled = enabled;
+
+            let ctx = Context {
+                config: &config,
+                db_conn: &db_conn,
+                node: &node,
+                cipher: &cipher,
+                price: &price,
+            };
+
+            for &(platform, id) in &[(Platform::Hangouts, ALICE), (Platform::Teams, "29:alice")] {
+                assert_eq!(
+                    send_tip(&ctx, &user(platform, id), &alice, id, one),
+                    Err("you cannot tip yourself".to_string()),
+                    "{} with custody {}",
+                    id,
+                    enabled
+                );
+            }
+        }
+
+        assert_eq!(
+            ledger::balance(&db_conn, &ledger::user_account(alice.id)).unwrap(),
+            Amount::from_nano_str("1").unwrap()
+        );
+        assert!(ledger::check(&db_conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_link_codes_until_a_link_succeeds() {
+        let db_conn = db::get_connection(":memory:").unwrap();
+        let node: NanoRpcClient = unreachable_node();
+        let cipher = KeyCipher::from_hex(MASTER_KEY).unwrap();
+        let price = PriceFeed::new(Vec::new(), Duration::from_secs(0));
+        let mut config = Config::default();
+        config.custody.enabled = true;
+        let ctx = Context {
+            config: &config,
+            db_conn: &db_conn,
+            node: &node,
+            cipher: &cipher,
+            price: &price,
+        };
+        let alice: node::Account = funded_account(&db_conn, ALICE, "29:alice");
+        let key = node::Key {
+            account: "nano_bob".to_string(),
+            public: "EF".to_string(),
+            private: "01".to_string(),
+        };
+        let bob: i64 =
+            db::add_account(&db_conn, &key, "wallet", "hangouts", "bob@example.com").unwrap();
+        let expires_at: i64 = Utc::now().timestamp() + 600;
+        db::add_link_code(&db_conn, "ABCDEFGH", bob, "hangouts", expires_at).unwrap();
+
+        let bob_here: User = user(Platform::Hangouts, "bob@example.com");
+        let same_platform = confirm_link(&ctx, &bob_here, "ABCDEFGH");
+        assert_eq!(
+            same_platform.text,
+            Some("Link codes have to be confirmed from the other platform".to_string())
+        );
+        assert!(db::get_link_code(&db_conn, "ABCDEFGH").is_ok());
+
+        // Alice's Teams identity points at a funded wallet, which must not be orphaned
+        confirm_link(&ctx, &user(Platform::Teams, "29:alice"), "ABCDEFGH");
+        assert_eq!(db::get_account(&db_conn, "teams", "29:alice").unwrap().id, alice.id);
+        assert!(db::get_link_code(&db_conn, "ABCDEFGH").is_ok());
+
+        let linked = confirm_link(&ctx, &user(Platform::Teams, "29:bob"), "ABCDEFGH");
+        assert!(linked.text.unwrap().starts_with("Linked!"));
+        assert_eq!(db::get_account(&db_conn, "teams", "29:bob").unwrap().id, bob);
+        assert!(db::get_link_code(&db_conn, "ABCDEFGH").is_err());
+    }
+
+    #[test]
+    fn email_addresses() {
+        let cases: &[(&str, bool)] = &[
+            ("bob@example.com", true),
+            ("Bob.Smith@Example.COM", true),
+            ("bob+tips@example.com", true),
+            ("bob_smith-1@mail.eu.example.co.uk", true),
+            ("b@x.io", true),
+            ("bob@example", false),
+            ("bob@.example.com", false),
+            ("bob@-example.com", false),
+            ("bob@example..com", false),
+            (".bob@example.com", false),
+            ("bob..smith@example.com", false),
+            ("bob@example.c", false),
+            ("bob example@example.com", false),
+            ("@example.com", false),
+            ("bob", false),
+            ("", false),
+        ];
+
+        for &(email, valid) in cases {
+            assert_eq!(validate_email_address(email), valid, "{}", email);
+        }
+    }
+
+    #[test]
+    fn typed_receivers() {
+        assert_eq!(validate_receiver(Platform::Hangouts, "bob@example.com"), Ok(()));
+        assert!(validate_receiver(Platform::Hangouts, "bob").is_err());
+        assert!(validate_receiver(Platform::Teams, "bob").is_err());
+        assert!(validate_receiver(Platform::Teams, "29:1abc").is_err());
+    }
+
+    #[test]
+    fn tip_arguments() {
+        let nano = |n: &str| AmountInput::Nano(Amount::from_nano_str(n).unwrap());
+
+        let cases: &[(&str, Result<(Vec<&str>, AmountInput, Share), &str>)] = &[
+            ("!tip bob@x.com 1", Ok((vec!["bob@x.com"], nano("1"), Share::Each))),
+            (
+                "!tip bob@x.com 1.5 NANO",
+                Ok((vec!["bob@x.com"], nano("1.5"), Share::Each)),
+            ),
+            (
+                "!tip a@x.com b@x.com a@x.com 2 split",
+                Ok((vec!["a@x.com", "b@x.com"], nano("2"), Share::Split)),
+            ),
+            ("!tip", Err("No receiver supplied")),
+            ("!tip bob@x.com", Err("No amount supplied")),
+            ("!tip bob@x.com split", Err("No amount supplied")),
+            (
+                "!tip bob@x.com lots",
+                Err("Argument 2: Could not parse amount `lots`, try `1.5`, `500 knano`, `3 raw`, `€2` or `2 USD`"),
+            ),
+            (
+                "!tip a@x.com b@x.com 0",
+                Err("Argument 3: Amount must be greater than zero"),
+            ),
+        ];
+
+        for &(ref text, ref expected) in cases {
+            let expected = expected.clone().map_err(|e| e.to_string());
+
+            assert_eq!(parse_tip_arguments(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn withdraw_arguments() {
+        let address = "xrb_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3";
+        let nano = |n: &str| AmountInput::Nano(Amount::from_nano_str(n).unwrap());
+
+        let (parsed, amount) = parse_withdraw_arguments(&format!("!withdraw {} 2", address))
+            .expect("valid withdrawal");
+
+        assert_eq!(parsed.to_string(), address.replace("xrb_", "nano_"));
+        assert_eq!(amount, nano("2"));
+
+        let typo = address.replace("ohr3", "ohr4");
+
+        assert_eq!(
+            parse_withdraw_arguments(&format!("!withdraw {} 2", typo)).err(),
+            Some(
+                "Could not parse wallet address: the checksum does not match, check for typos"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_withdraw_arguments("!withdraw").err(),
+            Some("No wallet address supplied".to_string())
+        );
+    }
+}
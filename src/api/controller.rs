@@ -1,7 +1,16 @@
-use api::coinmarketcap::get_nano_price_in_euros;
+use api::auth::BearerToken;
+use api::commands::Context;
 use api::hangouts;
+use api::price::{PriceFeed, PriceProvider};
+use api::ratelimit::{self, RateLimiter};
+use api::response::Message;
 use api::teams;
 use chrono::Utc;
+use config::Config;
+use db::KeyCipher;
+use node::NanoRpcClient;
+use rocket::http::Status;
+use rocket::response::Failure;
 use rocket::{Rocket, State};
 use rocket_contrib::Json;
 use rusqlite::Connection;
@@ -9,37 +18,147 @@ use std::sync::Mutex;
 
 #[post("/hangouts", format = "application/json", data = "<event>")]
 fn hangouts(
+    authorization: Option<BearerToken>,
+    keys: State<Option<hangouts::ChatKeys>>,
+    config: State<Config>,
     db_conn: State<Mutex<Connection>>,
+    node: State<NanoRpcClient>,
+    cipher: State<KeyCipher>,
+    price: State<PriceFeed>,
+    limiter: State<RateLimiter>,
     event: Json<hangouts::Event>,
-) -> Json<hangouts::ResponseMessage> {
-    Json(hangouts::handle_message(&db_conn, event.0))
+) -> Result<Json<hangouts::ResponseMessage>, Failure> {
+    let (hangouts_config, keys) = match (&config.hangouts, &*keys) {
+        (&Some(ref h), &Some(ref k)) => (h, k),
+        _ => {
+            println!("Received a Hangouts event but Hangouts is not configured");
+            return Err(Failure(Status::NotFound));
+        }
+    };
+
+    let token: Option<&str> = authorization.as_ref().map(|t| t.0.as_str());
+
+    if let Err(err) = hangouts::authenticate(token, &event, keys, hangouts_config) {
+        println!("Rejected Hangouts event: {}", err);
+        return Err(Failure(Status::Unauthorized));
+    }
+
+    if event.is_message() {
+        if let Err(wait) = limiter.check("hangouts", event.user_id(), event.space_id()) {
+            return Ok(Json(hangouts::render(Message::text(
+                ratelimit::throttled_message(wait),
+            ))));
+        }
+    }
+
+    let ctx = Context {
+        config: &config,
+        db_conn: &db_conn,
+        node: &node,
+        cipher: &cipher,
+        price: &price,
+    };
+
+    Ok(Json(hangouts::handle_message(&ctx, event.0)))
 }
 
 #[post("/teams", format = "application/json", data = "<activity>")]
 fn teams(
     activity: Json<teams::Activity>,
+    authorization: Option<BearerToken>,
+    keys: State<Option<teams::BotFrameworkKeys>>,
     bearer_token: State<Mutex<teams::TeamsToken>>,
+    config: State<Config>,
     db_conn: State<Mutex<Connection>>,
-) {
+    node: State<NanoRpcClient>,
+    cipher: State<KeyCipher>,
+    price: State<PriceFeed>,
+    limiter: State<RateLimiter>,
+) -> Result<(), Failure> {
+    let (teams_config, keys) = match (&config.teams, &*keys) {
+        (&Some(ref t), &Some(ref k)) => (t, k),
+        _ => {
+            println!("Received a Teams activity but Teams is not configured");
+            return Err(Failure(Status::NotFound));
+        }
+    };
+
+    let token: BearerToken = match authorization {
+        Some(t) => t,
+        None => return Err(Failure(Status::Unauthorized)),
+    };
+
+    if let Err(err) = teams::authenticate(&token.0, &activity, keys, teams_config) {
+        println!("Rejected Teams activity: {}", err);
+        return Err(Failure(Status::Unauthorized));
+    }
+
     println!("{:?}", activity.0);
 
-    match teams::handle_message(activity.0, &bearer_token, &db_conn) {
+    if activity.is_message() {
+        if let Err(wait) = limiter.check("teams", activity.user_id(), activity.space_id()) {
+            let message = Message::text(ratelimit::throttled_message(wait));
+
+            if let Err(err) = teams::reply(activity.0, message, &bearer_token, teams_config) {
+                println!("{}", err);
+            }
+
+            return Ok(());
+        }
+    }
+
+    let ctx = Context {
+        config: &config,
+        db_conn: &db_conn,
+        node: &node,
+        cipher: &cipher,
+        price: &price,
+    };
+
+    match teams::handle_message(activity.0, &bearer_token, teams_config, &ctx) {
         Ok(_) => println!("Teams success"),
         Err(err) => println!("{}", err),
     }
+
+    Ok(())
 }
 
 #[get("/")]
-fn moo() -> Json {
-    match get_nano_price_in_euros() {
-        Ok(r) => Json(json!(r)),
+fn moo(config: State<Config>, price: State<PriceFeed>) -> Json {
+    match price.quote(&config.price.default_currency) {
+        Ok(q) => Json(json!({
+            "price": q.price,
+            "currency": q.currency,
+            "timestamp": q.timestamp.to_rfc3339(),
+            "source": q.source,
+        })),
         Err(_) => Json(json!("Oooops")),
     }
 }
 
-pub fn rocket(db_conn: Mutex<Connection>) -> Rocket {
-    Rocket::ignite()
+pub fn rocket(
+    rocket: Rocket,
+    config: Config,
+    db_conn: Mutex<Connection>,
+    node: NanoRpcClient,
+    cipher: KeyCipher,
+) -> Rocket {
+    let chat_keys: Option<hangouts::ChatKeys> =
+        config.hangouts.as_ref().map(hangouts::ChatKeys::new);
+    let teams_keys: Option<teams::BotFrameworkKeys> =
+        config.teams.as_ref().map(teams::BotFrameworkKeys::new);
+    let price: PriceFeed = PriceFeed::from_config(&config.price);
+    let limiter: RateLimiter = RateLimiter::new(&config.rate_limit);
+
+    rocket
+        .manage(chat_keys)
+        .manage(teams_keys)
+        .manage(config)
         .manage(db_conn)
+        .manage(node)
+        .manage(cipher)
+        .manage(price)
+        .manage(limiter)
         .manage(Mutex::new(teams::TeamsToken {
             value: "initial_token".to_string(),
             expire_date: Utc::now(),
@@ -1,11 +1,18 @@
-use db;
-use node;
-use regex::Regex;
-use rusqlite::Connection;
-use serde::ser::{Serialize, SerializeStruct, Serializer};
-use std::any::Any;
+use api::auth::{self, AuthError, KeySet, KeySource};
+use api::commands::{self, Context, Platform, User};
+use api::notify::{self, Notifier};
+use api::response;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use config::HangoutsConfig;
+use db::{self, ConversationReference};
+use hyper::header::ContentType;
+use ring::constant_time;
+use serde_json;
 use std::error::Error;
+use std::fs;
 use std::sync::Mutex;
+use std::time::Duration;
+use url::form_urlencoded;
 
 #[derive(Deserialize, Debug)]
 pub struct Event {
@@ -19,6 +26,8 @@ pub struct Event {
     message: Message,
 
     space: Space,
+
+    #[serde(default)]
     token: String,
 
     #[serde(rename = "type")]
@@ -37,13 +46,15 @@ struct Space {
 
 #[derive(Deserialize, Debug, Default)]
 struct Sender {
-    #[serde(rename = "avatarUrl")]
+    #[serde(rename = "avatarUrl", default)]
     avatar_url: String,
 
     #[serde(rename = "displayName")]
     display_name: String,
 
+    #[serde(default)]
     email: String,
+
     name: String,
 
     #[serde(rename = "type")]
@@ -73,6 +84,30 @@ struct Message {
     space: Space,
     text: String,
     thread: Thread,
+
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Annotation {
+    #[serde(rename = "type")]
+    annotation_type: String,
+
+    /// Position of the annotated text in the message, counted in characters.
+    #[serde(rename = "startIndex", default)]
+    start_index: usize,
+
+    #[serde(default)]
+    length: usize,
+
+    #[serde(rename = "userMention", default)]
+    user_mention: Option<UserMention>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct UserMention {
+    user: Sender,
 }
 
 #[derive(Serialize)]
@@ -83,19 +118,33 @@ pub struct ResponseMessage {
 
 #[derive(Serialize)]
 struct Card {
+    header: CardHeader,
     sections: Vec<Section>,
 }
 
+#[derive(Serialize)]
+struct CardHeader {
+    title: String,
+}
+
 #[derive(Serialize)]
 struct Section {
-    header: String,
-    widgets: Vec<Box<Widget>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<String>,
+
+    widgets: Vec<Widget>,
 }
 
 #[derive(Serialize)]
-struct Image {
-    #[serde(rename = "imageUrl")]
-    image_url: String,
+enum Widget {
+    #[serde(rename = "keyValue")]
+    KeyValue(KeyValue),
+
+    #[serde(rename = "image")]
+    Image(Image),
+
+    #[serde(rename = "buttons")]
+    Buttons(Vec<Button>),
 }
 
 #[derive(Serialize)]
@@ -107,94 +156,320 @@ struct KeyValue {
 }
 
 #[derive(Serialize)]
-struct ImageWidget {
-    image: Image,
+struct Image {
+    #[serde(rename = "imageUrl")]
+    image_url: String,
 }
 
 #[derive(Serialize)]
-struct KeyValueWidget {
-    #[serde(rename = "keyValue")]
-    key_value: KeyValue,
+struct Button {
+    #[serde(rename = "textButton")]
+    text_button: TextButton,
+}
+
+#[derive(Serialize)]
+struct TextButton {
+    text: String,
+
+    #[serde(rename = "onClick")]
+    on_click: OnClick,
 }
 
-trait Widget {
-    fn as_any(&self) -> &Any;
+#[derive(Serialize)]
+struct OnClick {
+    #[serde(rename = "openLink")]
+    open_link: OpenLink,
 }
 
-impl Serialize for Box<Widget> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self.as_any().downcast_ref::<ImageWidget>() {
-            Some(res) => {
-                let mut widget_serializer = serializer.serialize_struct("ImageWidget", 1)?;
-                widget_serializer.serialize_field("image", &res.image)?;
+#[derive(Serialize)]
+struct OpenLink {
+    url: String,
+}
 
-                widget_serializer.end()
-            }
-            None => {
-                let key_value: &KeyValueWidget =
-                    match self.as_any().downcast_ref::<KeyValueWidget>() {
-                        Some(b) => b,
-                        None => panic!("Unknown type!"),
-                    };
+/// Signing keys of the Google Chat service account, kept apart from other key sets in managed
+/// state.
+pub struct ChatKeys(pub KeySet);
+
+impl ChatKeys {
+    pub fn new(hangouts_config: &HangoutsConfig) -> ChatKeys {
+        let source = match hangouts_config.openid_keys_file {
+            Some(ref path) => KeySource::File(path.to_owned()),
+            None => KeySource::Url(hangouts_config.openid_keys_url.to_owned()),
+        };
+
+        ChatKeys(KeySet::new(source, Duration::from_secs(24 * 60 * 60)))
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatClaims {}
+
+/// Posts messages to direct message spaces through the Google Chat API, authenticated as the
+/// bot's service account.
+pub struct ChatNotifier {
+    chat_api_url: String,
+    account: ServiceAccount,
+    token: Mutex<Option<(DateTime<Utc>, String)>>,
+}
+
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct ServiceAccountClaims<'a> {
+    iss: &'a str,
+    scope: &'static str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+impl ChatNotifier {
+    pub fn new(service_account_file: &str, chat_api_url: &str) -> Result<ChatNotifier, Box<Error>> {
+        let account: ServiceAccount =
+            serde_json::from_str(&fs::read_to_string(service_account_file)?)?;
+
+        Ok(ChatNotifier {
+            chat_api_url: chat_api_url.trim_right_matches('/').to_string(),
+            account,
+            token: Mutex::new(None),
+        })
+    }
 
-                let mut widget_serializer = serializer.serialize_struct("KeyValue", 1)?;
-                widget_serializer.serialize_field("keyValue", &key_value.key_value)?;
+    /// Exchanges a self-signed assertion for an access token, reused until a minute before it
+    /// expires.
+    fn access_token(&self) -> Result<String, Box<Error>> {
+        let mut token = self.token.lock().expect("chat token lock");
 
-                widget_serializer.end()
+        if let Some((expires_at, ref value)) = *token {
+            if expires_at > Utc::now() {
+                return Ok(value.to_owned());
             }
         }
+
+        let now: i64 = Utc::now().timestamp();
+        let assertion: String = auth::sign(
+            &ServiceAccountClaims {
+                iss: &self.account.client_email,
+                scope: "https://www.googleapis.com/auth/chat.bot",
+                aud: &self.account.token_uri,
+                iat: now,
+                exp: now + 3600,
+            },
+            &self.account.private_key,
+            None,
+        )?;
+
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer")
+            .append_pair("assertion", &assertion)
+            .finish();
+
+        let response: AccessToken = serde_json::from_slice(&notify::post(
+            &self.account.token_uri,
+            ContentType::form_url_encoded(),
+            body,
+            None,
+        )?)?;
+
+        *token = Some((
+            Utc::now() + ChronoDuration::seconds(response.expires_in - 60),
+            response.access_token.to_owned(),
+        ));
+
+        Ok(response.access_token)
     }
 }
 
-impl Widget for ImageWidget {
-    fn as_any(&self) -> &Any {
-        self
+impl Notifier for ChatNotifier {
+    fn platform(&self) -> &str {
+        "hangouts"
+    }
+
+    fn notify(
+        &self,
+        reference: &ConversationReference,
+        message: response::Message,
+    ) -> Result<(), Box<Error>> {
+        let token: String = self.access_token()?;
+        let url = format!("{}/{}/messages", self.chat_api_url, reference.conversation);
+
+        notify::post(
+            &url,
+            ContentType::json(),
+            serde_json::to_string(&render(message))?,
+            Some(&token),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Accepts a bearer token from Google Chat when a project number is configured, otherwise falls
+/// back to the verification token sent in the event body. With a project number the bearer token
+/// is required, a request without one does not get to try the verification token.
+pub fn authenticate(
+    bearer_token: Option<&str>,
+    event: &Event,
+    keys: &ChatKeys,
+    hangouts_config: &HangoutsConfig,
+) -> Result<(), AuthError> {
+    if let Some(ref project_number) = hangouts_config.project_number {
+        let token: &str = match bearer_token {
+            Some(t) => t,
+            None => return Err(AuthError::MissingToken),
+        };
+
+        let _: (ChatClaims, auth::Jwk) = auth::verify(
+            token,
+            &keys.0,
+            &["chat@system.gserviceaccount.com"],
+            project_number,
+        )?;
+
+        return Ok(());
+    }
+
+    match hangouts_config.verification_token {
+        Some(ref expected) if !event.token.is_empty() => {
+            let (expected, received) = (expected.as_bytes(), event.token.as_bytes());
+
+            match constant_time::verify_slices_are_equal(expected, received) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(AuthError::Claim("token")),
+            }
+        }
+        _ => Err(AuthError::MissingToken),
     }
 }
 
-impl Widget for KeyValueWidget {
-    fn as_any(&self) -> &Any {
-        self
+impl Event {
+    pub fn is_message(&self) -> bool {
+        self.event_type.trim() == "MESSAGE"
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user.email
+    }
+
+    pub fn space_id(&self) -> &str {
+        &self.space.name
     }
 }
 
-pub fn handle_message(db_conn: &Mutex<Connection>, event: Event) -> ResponseMessage {
+pub fn handle_message(ctx: &Context, event: Event) -> ResponseMessage {
+    remember_conversation(ctx, &event);
+
     match event.event_type.trim() {
-        "ADDED_TO_SPACE" => ResponseMessage {
-            text: Some(format!(
-                "Hello and thanks for adding me, *{}*. For help type `!help`",
-                event.user.display_name
-            )),
-            cards: None,
-        },
-        "MESSAGE" => parse_text(&event.message.text, &event.user, db_conn),
-        _ => ResponseMessage {
-            text: Some("Unsupported event".to_string()),
-            cards: None,
-        },
+        "ADDED_TO_SPACE" => render(response::Message::text(format!(
+            "Hello and thanks for adding me, *{}*. For help type `!help`",
+            event.user.display_name
+        ))),
+        "MESSAGE" => parse_text(ctx, &event.message, &event.user, event.space.message_type == "DM"),
+        _ => render(response::Message::text("Unsupported event")),
     }
 }
 
-fn parse_text(text: &str, user: &Sender, db_conn: &Mutex<Connection>) -> ResponseMessage {
-    match remove_bot_name_from_text(text).trim() {
-        "!help" => ResponseMessage { text: Some("Available commands: `!balance` `!deposit` `!tip receiver_email amount` `!withdraw wallet_address`".to_string()), cards: None },        
-        "!balance" => get_balance(&user.email, db_conn),
-        "!deposit" => get_deposit_response(&user, db_conn),
-        t => if t.starts_with("!tip") { 
-                try_tip(db_conn, &t, &user.email)
-            }
-            else if t.starts_with("!withdraw") {
-                ResponseMessage { text: Some("Not implemented yet".to_string()), cards: None }
-            }
-            else {
-                ResponseMessage { text: Some(format!("Did not quite catch that, *{}*, type `!help` for help", user.display_name)), cards: None }
-            }
+/// Keeps the direct message space of the sender, the only place the bot may notify them in.
+fn remember_conversation(ctx: &Context, event: &Event) {
+    if event.space.message_type != "DM" || event.user.email.is_empty() {
+        return;
+    }
+
+    let reference = ConversationReference {
+        platform: Platform::Hangouts.as_str().to_string(),
+        external_id: event.user.email.to_owned(),
+        conversation: event.space.name.to_owned(),
+        service_url: String::new(),
+        bot_id: String::new(),
+        tenant_id: String::new(),
+    };
+
+    if let Err(e) = db::save_conversation(ctx.db_conn, &reference, &Utc::now().to_rfc3339()) {
+        println!("Could not store the conversation of {}: {}", event.user.email, e);
     }
 }
 
+fn parse_text(ctx: &Context, message: &Message, sender: &Sender, direct: bool) -> ResponseMessage {
+    let user = User {
+        platform: Platform::Hangouts,
+        id: sender.email.to_owned(),
+        display_name: sender.display_name.to_owned(),
+    };
+
+    let (text, mentions) = resolve_mentions(message);
+
+    render(commands::handle(ctx, &text, &user, &mentions, direct))
+}
+
+/// Replaces each mention of a person with the email it resolves to, at the position its
+/// annotation gives, and drops the mention of the bot.
+fn resolve_mentions(message: &Message) -> (String, Vec<User>) {
+    let mut text: String = message.text.to_owned();
+    let mut mentions: Vec<User> = Vec::new();
+
+    let mut annotations: Vec<(&Annotation, &Sender)> = get_mentioned_users(message);
+    // From the end of the text backwards, so a replacement cannot shift the positions still to go
+    annotations.sort_by(|a, b| b.0.start_index.cmp(&a.0.start_index));
+
+    for (annotation, mentioned) in annotations {
+        let (from, to) = match char_range(&text, annotation.start_index, annotation.length) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        text = format!("{}{}{}", &text[..from], mentioned.email, &text[to..]);
+
+        mentions.push(User {
+            platform: Platform::Hangouts,
+            id: mentioned.email.to_owned(),
+            display_name: mentioned.display_name.to_owned(),
+        });
+    }
+
+    mentions.reverse();
+
+    (remove_bot_name_from_text(&text).to_string(), mentions)
+}
+
+/// People mentioned in the message, leaving out bots and anyone without a known email.
+fn get_mentioned_users(message: &Message) -> Vec<(&Annotation, &Sender)> {
+    message
+        .annotations
+        .iter()
+        .filter(|a| a.annotation_type == "USER_MENTION")
+        .filter_map(|a| a.user_mention.as_ref().map(|m| (a, &m.user)))
+        .filter(|&(_, u)| u.sender_type == "HUMAN" && !u.email.is_empty())
+        .collect()
+}
+
+/// Byte offsets of `length` characters from character `start` of `text`, if they are in it.
+fn char_range(text: &str, start: usize, length: usize) -> Option<(usize, usize)> {
+    if length == 0 {
+        return None;
+    }
+
+    let mut boundaries = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(text.len()));
+
+    let from: usize = boundaries.nth(start)?;
+    let to: usize = boundaries.nth(length - 1)?;
+
+    Some((from, to))
+}
+
 fn remove_bot_name_from_text(text: &str) -> &str {
     if text.trim().starts_with("@Rusty Nanobot") {
         match text.split("@Rusty Nanobot").nth(1) {
@@ -206,294 +481,229 @@ fn remove_bot_name_from_text(text: &str) -> &str {
     }
 }
 
-fn get_deposit_response(user: &Sender, db_conn: &Mutex<Connection>) -> ResponseMessage {
-    let acc: node::Account = match try_get_account(&user.email, db_conn) {
-        Ok(a) => a,
-        Err(_) => {
-            return ResponseMessage {
-                text: Some("There was an error fetching the account".to_string()),
-                cards: None,
-            }
-        }
-    };
-
+pub fn render(message: response::Message) -> ResponseMessage {
     ResponseMessage {
-        text: None,
-        cards: Some(vec![Card {
-            sections: vec![
-                Section {
-                    header: "Deposit".to_string(),
-                    widgets: vec![
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "To".to_string(),
-                                content: user.email.to_owned(),
-                            },
-                        }),
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "Wallet".to_string(),
-                                content: acc.account.to_owned(),
-                            },
-                        }),
-                    ],
-                },
-                Section {
-                    header: "Scan QR Code using Nano mobile wallet".to_string(),
-                    widgets: vec![Box::new(ImageWidget {
-                        image: Image {
-                            image_url: format!(
-                                "https://api.qrserver.com/v1/create-qr-code/?data={}",
-                                acc.account
-                            ),
-                        },
-                    })],
-                },
-            ],
-        }]),
+        text: message.text,
+        cards: if message.cards.is_empty() {
+            None
+        } else {
+            Some(message.cards.into_iter().map(render_card).collect())
+        },
     }
 }
 
-fn try_tip(db_conn: &Mutex<Connection>, text_args: &str, sender_email: &str) -> ResponseMessage {
-    let tip_args: (&str, &str) = match parse_tip_arguments(text_args) {
-        Ok(a) => a,
-        Err(e) => {
-            return ResponseMessage {
-                text: Some(e),
-                cards: None,
-            }
-        }
-    };
-
-    let receiver_acc: node::Account = match try_get_account(tip_args.0, db_conn) {
-        Ok(a) => a,
-        Err(_) => {
-            return ResponseMessage {
-                text: Some("There was an error fetching the receiver account".to_string()),
-                cards: None,
-            }
-        }
-    };
-
-    let sender_acc: node::Account = match try_get_account(sender_email, db_conn) {
-        Ok(a) => a,
-        Err(_) => {
-            return ResponseMessage {
-                text: Some("There was an error fetching the sender account".to_string()),
-                cards: None,
-            }
-        }
-    };
-
-    match node::send(
-        &sender_acc.wallet,
-        &sender_acc.account,
-        &receiver_acc.account,
-        tip_args.1,
-    ) {
-        Ok(_) => ResponseMessage {
-            text: None,
-            cards: Some(vec![Card {
-                sections: vec![Section {
-                    header: "Tip sent!".to_string(),
-                    widgets: vec![
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "From".to_string(),
-                                content: sender_email.to_owned(),
-                            },
-                        }),
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "To".to_string(),
-                                content: tip_args.0.to_owned(),
-                            },
-                        }),
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "Wallet".to_string(),
-                                content: receiver_acc.account.to_owned(),
-                            },
-                        }),
-                        Box::new(KeyValueWidget {
-                            key_value: KeyValue {
-                                top_label: "Amount".to_string(),
-                                content: tip_args.1.to_owned(),
-                            },
-                        }),
-                    ],
-                }],
-            }]),
-        },
-        Err(_) => ResponseMessage {
-            text: Some("There was an error sending the tip".to_string()),
-            cards: None,
-        },
+fn render_card(card: response::Card) -> Card {
+    Card {
+        header: CardHeader { title: card.header },
+        sections: card
+            .sections
+            .into_iter()
+            .map(|section| Section {
+                header: section.header,
+                widgets: render_widgets(section.widgets),
+            })
+            .collect(),
     }
 }
 
-fn parse_tip_arguments(text_args: &str) -> Result<(&str, &str), String> {
-    let mut args = text_args.split_whitespace();
-
-    let email: &str = match args.nth(1) {
-        Some(email) => {
-            if match validate_email_address(email) {
-                Ok(r) => r,
-                Err(_) => false,
-            } {
-                email
-            } else {
-                return Err("Could not parse email address".to_string());
+fn render_widgets(widgets: Vec<response::Widget>) -> Vec<Widget> {
+    let mut rendered: Vec<Widget> = vec![];
+    let mut buttons: Vec<Button> = vec![];
+
+    for widget in widgets {
+        match widget {
+            response::Widget::KeyValue { label, content } => {
+                rendered.push(Widget::KeyValue(KeyValue {
+                    top_label: label,
+                    content,
+                }))
             }
-        }
-        _ => return Err("No email supplied".to_string()),
-    };
-
-    let amount: &str = match args.next() {
-        Some(amount) => {
-            if match validate_tip_amont(amount) {
-                Ok(r) => r,
-                Err(_) => false,
-            } {
-                amount
-            } else {
-                return Err("Could not parse amount".to_string());
+            response::Widget::Image { url } => {
+                rendered.push(Widget::Image(Image { image_url: url }))
             }
+            response::Widget::QrCode { data } => rendered.push(Widget::Image(Image {
+                image_url: response::qr_code_url(&data),
+            })),
+            response::Widget::Button { text, url } => buttons.push(Button {
+                text_button: TextButton {
+                    text,
+                    on_click: OnClick {
+                        open_link: OpenLink { url },
+                    },
+                },
+            }),
         }
-        _ => return Err("No amount supplied".to_string()),
-    };
+    }
 
-    Ok((email, amount))
+    if !buttons.is_empty() {
+        rendered.push(Widget::Buttons(buttons));
+    }
+
+    rendered
 }
 
-fn try_get_account(user_email: &str, db_conn: &Mutex<Connection>) -> Result<node::Account, String> {
-    let has_account: bool = match db::get_account_hangouts(db_conn, user_email) {
-        Ok(_) => true,
-        Err(_) => false,
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::auth::tests::{self as keys, KID};
+
+    const ISSUER: &str = "chat@system.gserviceaccount.com";
+    const PROJECT_NUMBER: &str = "1234567890";
+    const VERIFICATION_TOKEN: &str = "verification-token";
+
+    fn event(token: &str) -> Event {
+        serde_json::from_value(json!({
+            "eventTime": "2018-06-01T12:00:00Z",
+            "space": { "name": "spaces/AAAA", "type": "DM" },
+            "token": token,
+            "type": "MESSAGE",
+            "user": { "displayName": "Bob", "name": "users/1", "type": "HUMAN" },
+        })).expect("event")
+    }
 
-    if !has_account {
-        return match try_create_account(&user_email, db_conn) {
-            "Account has been succesfully created, to check your balance type `!balance`" => {
-                return match db::get_account_hangouts(db_conn, user_email) {
-                    Ok(a) => Ok(a),
-                    Err(_) => Err("An error has occured".to_string()),
-                }
-            }
-            _ => Err("An error has occured".to_string()),
-        };
-    };
+    #[test]
+    fn renders_cards_as_google_chat_widgets() {
+        let card = response::Card::new("Balance").section(
+            response::Section::with_header("Account")
+                .key_value("Balance", "1.000000 NANO")
+                .image("https://example.com/logo.png")
+                .button("Explorer", "https://example.com/account"),
+        );
+
+        let rendered = serde_json::to_value(render(response::Message::card(card))).unwrap();
+        let section = &rendered["cards"][0]["sections"][0];
+
+        assert_eq!(rendered["cards"][0]["header"]["title"], "Balance");
+        assert_eq!(section["header"], "Account");
+        assert_eq!(
+            section["widgets"],
+            json!([
+                { "keyValue": { "topLabel": "Balance", "content": "1.000000 NANO" } },
+                { "image": { "imageUrl": "https://example.com/logo.png" } },
+                { "buttons": [{ "textButton": {
+                    "text": "Explorer",
+                    "onClick": { "openLink": { "url": "https://example.com/account" } }
+                } }] },
+            ])
+        );
+    }
 
-    match db::get_account_hangouts(db_conn, user_email) {
-        Ok(a) => Ok(a),
-        Err(_) => Err("An error has occured".to_string()),
+    fn hangouts_config(project_number: Option<&str>) -> HangoutsConfig {
+        serde_json::from_value(json!({
+            "verification_token": VERIFICATION_TOKEN,
+            "project_number": project_number,
+        })).expect("hangouts config")
     }
-}
 
-fn try_create_account(user_email: &str, db_conn: &Mutex<Connection>) -> &'static str {
-    let wallet: node::Wallet = match node::create_new_wallet() {
-        Ok(w) => w,
-        Err(_) => return "An error has occured attempting to create a wallet",
-    };
+    fn authenticate_with(
+        bearer_token: Option<&str>,
+        event_token: &str,
+        project_number: Option<&str>,
+    ) -> Result<(), String> {
+        let (key_set, _) = keys::key_set(&[KID]);
+
+        authenticate(
+            bearer_token,
+            &event(event_token),
+            &ChatKeys(key_set),
+            &hangouts_config(project_number),
+        ).map_err(|e| e.to_string())
+    }
 
-    let key: node::Key = match node::create_new_key() {
-        Ok(k) => k,
-        Err(_) => return "An error has occured attempting to create a key",
-    };
+    #[test]
+    fn requires_a_valid_bearer_token_with_a_project_number() {
+        let project = Some(PROJECT_NUMBER);
+        let valid: String = keys::token(KID, &keys::claims(ISSUER, PROJECT_NUMBER, json!({})));
+        let other_project: String = keys::token(KID, &keys::claims(ISSUER, "42", json!({})));
+
+        assert_eq!(authenticate_with(Some(&valid), "", project), Ok(()));
+        assert!(authenticate_with(Some(&other_project), VERIFICATION_TOKEN, project).is_err());
+        assert!(authenticate_with(Some("not.a-token"), VERIFICATION_TOKEN, project).is_err());
+        assert_eq!(
+            authenticate_with(None, VERIFICATION_TOKEN, project),
+            Err(AuthError::MissingToken.to_string())
+        );
+    }
 
-    match node::add_key_to_wallet(&wallet.wallet, &key.private) {
-        Ok(_) => match db::add_account_hangouts(db_conn, &key, user_email, &wallet.wallet) {
-            Ok(_) => "Account has been succesfully created, to check your balance type `!balance`",
-            Err(_) => "An error has occured attempting to create an account",
-        },
-        Err(_) => "An error has occured attempting to add key to a wallet",
+    #[test]
+    fn checks_the_verification_token_without_a_project_number() {
+        assert_eq!(authenticate_with(None, VERIFICATION_TOKEN, None), Ok(()));
+        assert_eq!(
+            authenticate_with(None, "guessed-token", None),
+            Err(AuthError::Claim("token").to_string())
+        );
+        assert_eq!(
+            authenticate_with(None, "", None),
+            Err(AuthError::MissingToken.to_string())
+        );
     }
-}
 
-fn get_balance(user_email: &str, db_conn: &Mutex<Connection>) -> ResponseMessage {
-    let acc: node::Account = match try_get_account(user_email, db_conn) {
-        Ok(a) => a,
-        Err(_) => {
-            return ResponseMessage {
-                text: Some("An error has occured fetching the account".to_string()),
-                cards: None,
-            }
+    fn mention(start_index: usize, name: &str, email: &str, sender_type: &str) -> Annotation {
+        Annotation {
+            annotation_type: "USER_MENTION".to_string(),
+            start_index,
+            length: name.chars().count() + 1,
+            user_mention: Some(UserMention {
+                user: Sender {
+                    display_name: name.to_string(),
+                    email: email.to_string(),
+                    sender_type: sender_type.to_string(),
+                    ..Sender::default()
+                },
+            }),
         }
-    };
+    }
 
-    let bal: node::Balance = match node::get_balance(acc.account) {
-        Ok(b) => b,
-        Err(_) => {
-            return ResponseMessage {
-                text: Some("An error has occured fetching the balance".to_string()),
-                cards: None,
-            }
+    fn message(text: &str, annotations: Vec<Annotation>) -> Message {
+        Message {
+            text: text.to_string(),
+            annotations,
+            ..Message::default()
         }
-    };
+    }
 
-    let converted_balances: (String, String) = (
-        match convert_raw_to_nano(&bal.balance) {
-            Ok(b) => b.to_string(),
-            Err(_) => {
-                return ResponseMessage {
-                    text: Some("An error has occured converting the balance".to_string()),
-                    cards: None,
-                }
-            }
-        },
-        match convert_raw_to_nano(&bal.pending) {
-            Ok(p) => p.to_string(),
-            Err(_) => {
-                return ResponseMessage {
-                    text: Some("An error has occured fetching the balance".to_string()),
-                    cards: None,
-                }
-            }
-        },
-    );
+    #[test]
+    fn replaces_mentions_where_they_are() {
+        let text = "@Rusty Nanobot !tip @Al @Alice @Al 1";
+        let (resolved, mentions) = resolve_mentions(&message(
+            text,
+            vec![
+                mention(0, "Rusty Nanobot", "", "BOT"),
+                mention(20, "Al", "al@example.com", "HUMAN"),
+                mention(24, "Alice", "alice@example.com", "HUMAN"),
+                mention(31, "Al", "al@example.com", "HUMAN"),
+            ],
+        ));
 
-    ResponseMessage {
-        text: None,
-        cards: Some(vec![Card {
-            sections: vec![Section {
-                header: "Balance".to_string(),
-                widgets: vec![
-                    Box::new(KeyValueWidget {
-                        key_value: KeyValue {
-                            top_label: "Current".to_string(),
-                            content: format!("{} NANO, €", converted_balances.0),
-                        },
-                    }),
-                    Box::new(KeyValueWidget {
-                        key_value: KeyValue {
-                            top_label: "Pending".to_string(),
-                            content: format!("{} NANO, €", converted_balances.1),
-                        },
-                    }),
-                ],
-            }],
-        }]),
-    }
-}
+        assert_eq!(
+            resolved.trim(),
+            "!tip al@example.com alice@example.com al@example.com 1"
+        );
 
-fn validate_email_address(email: &str) -> Result<bool, Box<Error>> {
-    Ok(Regex::new(r"^[A-Z0-9._%+-]+@[A-Z0-9.-]+\\.[A-Z]{2,6}$")?.is_match(email))
-}
+        let ids: Vec<&str> = mentions.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["al@example.com", "alice@example.com", "al@example.com"]);
+    }
 
-fn validate_tip_amont(amount: &str) -> Result<bool, Box<Error>> {
-    Ok(Regex::new(r"^[1-9][0-9]*$")?.is_match(amount))
-}
+    #[test]
+    fn leaves_unannotated_names_alone() {
+        let text = "Ünïcødé @Al, not Al";
+        let (resolved, _) = resolve_mentions(&message(
+            text,
+            vec![mention(8, "Al", "al@example.com", "HUMAN")],
+        ));
 
-fn convert_raw_to_nano(raw_amount: &str) -> Result<u128, String> {
-    match u128::from_str_radix(raw_amount, 10) {
-        Ok(a) => Ok(a / 1_000_000_000_000_000_000_000_000),
-        Err(_) => Err("Error converting raw to nano".to_string()),
+        assert_eq!(resolved, "Ünïcødé al@example.com, not Al");
     }
-}
 
-fn convert_raw_from_nano(nano_amount: &str) -> Result<u128, String> {
-    match u128::from_str_radix(nano_amount, 10) {
-        Ok(a) => Ok(a * 1_000_000_000_000_000_000_000_000),
-        Err(_) => Err("Error converting nano to raw".to_string()),
+    #[test]
+    fn skips_annotations_outside_the_text() {
+        let text = "!tip @Al 1";
+        let (resolved, mentions) = resolve_mentions(&message(
+            text,
+            vec![mention(9, "Al", "al@example.com", "HUMAN")],
+        ));
+
+        assert_eq!(resolved, text);
+        assert!(mentions.is_empty());
     }
 }
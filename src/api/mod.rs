@@ -1,4 +1,9 @@
-mod coinmarketcap;
+mod auth;
+mod commands;
 pub mod controller;
 mod hangouts;
+pub mod notify;
+pub mod price;
+mod ratelimit;
+mod response;
 mod teams;
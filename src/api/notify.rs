@@ -0,0 +1,158 @@
+use api::hangouts::ChatNotifier;
+use api::response::{Card, Message, Section};
+use api::teams::TeamsNotifier;
+use config::Config;
+use db::ConversationReference;
+use futures::{Future, Stream};
+use hyper::header::{Authorization, Bearer, ContentLength, ContentType};
+use hyper::{Client, Method, Request, Uri};
+use hyper_tls::HttpsConnector;
+use node::Amount;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio_core::reactor::Core;
+
+/// Sends a message to a user outside of a reply, through the conversation reference stored when
+/// the user last wrote to the bot.
+pub trait Notifier: Send + Sync {
+    /// Platform the notifier serves, as stored in `conversations.platform`.
+    fn platform(&self) -> &str;
+
+    fn notify(&self, reference: &ConversationReference, message: Message) -> Result<(), Box<Error>>;
+}
+
+/// Logs messages instead of sending them, for running without platform credentials and in tests.
+pub struct MockNotifier {
+    platform: &'static str,
+    sent: Mutex<Vec<(String, String)>>,
+}
+
+impl MockNotifier {
+    pub fn new(platform: &'static str) -> MockNotifier {
+        MockNotifier {
+            platform,
+            sent: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Users notified so far, with the text or card header each was sent.
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<(String, String)> {
+        self.sent.lock().expect("mock notifier lock").clone()
+    }
+}
+
+impl Notifier for MockNotifier {
+    fn platform(&self) -> &str {
+        self.platform
+    }
+
+    fn notify(
+        &self,
+        reference: &ConversationReference,
+        message: Message,
+    ) -> Result<(), Box<Error>> {
+        let summary: String = match (message.text, message.cards.first()) {
+            (Some(text), _) => text,
+            (None, Some(card)) => card.header.to_owned(),
+            (None, None) => String::new(),
+        };
+
+        println!(
+            "Would notify {} on {}: {}",
+            reference.external_id, self.platform, summary
+        );
+
+        self.sent
+            .lock()
+            .expect("mock notifier lock")
+            .push((reference.external_id.to_owned(), summary));
+
+        Ok(())
+    }
+}
+
+/// Notifiers for every platform able to message users on its own: Hangouts needs a service
+/// account, Teams only its app credentials.
+pub fn from_config(config: &Config) -> Vec<Arc<Notifier>> {
+    let mut notifiers: Vec<Arc<Notifier>> = Vec::new();
+
+    if config.notifications.mock {
+        notifiers.push(Arc::new(MockNotifier::new("hangouts")));
+        notifiers.push(Arc::new(MockNotifier::new("teams")));
+
+        return notifiers;
+    }
+
+    if let Some(ref hangouts) = config.hangouts {
+        if let Some(ref path) = hangouts.service_account_file {
+            match ChatNotifier::new(path, &hangouts.chat_api_url) {
+                Ok(n) => notifiers.push(Arc::new(n)),
+                Err(e) => println!("Hangouts notifications are off: {}", e),
+            }
+        }
+    }
+
+    if let Some(ref teams) = config.teams {
+        notifiers.push(Arc::new(TeamsNotifier::new(teams)));
+    }
+
+    notifiers
+}
+
+pub fn deposit_message(amount: Amount, source: Option<&str>, block: &str) -> Message {
+    let mut section: Section = Section::new().key_value("Amount", amount.to_string());
+
+    if let Some(source) = source {
+        section = section.key_value("From", source);
+    }
+
+    Message::card(
+        Card::new(format!("You received {}", amount)).section(
+            section.key_value("Block", block).button(
+                "View block",
+                format!("https://nanocrawler.cc/explorer/block/{}", block),
+            ),
+        ),
+    )
+}
+
+/// Posts `body` and returns the response body, failing on any status other than success.
+pub fn post(
+    url: &str,
+    content_type: ContentType,
+    body: String,
+    bearer_token: Option<&str>,
+) -> Result<Vec<u8>, Box<Error>> {
+    let uri: Uri = url.parse()?;
+    let mut core = Core::new()?;
+    let client = Client::configure()
+        .connector(HttpsConnector::new(4, &core.handle())?)
+        .build(&core.handle());
+    let mut req = Request::new(Method::Post, uri);
+
+    req.headers_mut().set(content_type);
+    req.headers_mut().set(ContentLength(body.len() as u64));
+
+    if let Some(token) = bearer_token {
+        req.headers_mut().set(Authorization(Bearer {
+            token: token.to_string(),
+        }));
+    }
+
+    req.set_body(body);
+
+    let work = client.request(req).and_then(|res| {
+        let status = res.status();
+
+        res.body().concat2().map(move |body| (status, body))
+    });
+
+    let (status, body) = core.run(work)?;
+
+    if !status.is_success() {
+        return Err(From::from(format!("{} returned {}", url, status)));
+    }
+
+    Ok(body.to_vec())
+}
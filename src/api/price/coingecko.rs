@@ -0,0 +1,58 @@
+use api::price::{self, PriceError, PriceProvider, Quote};
+use chrono::{TimeZone, Utc};
+use serde_json::{self, Value};
+use std::collections::HashMap;
+
+/// CoinGecko public API, usable without a key.
+pub struct CoinGecko {
+    price_url: String,
+}
+
+impl CoinGecko {
+    pub fn new(price_url: &str) -> CoinGecko {
+        CoinGecko {
+            price_url: price_url.to_string(),
+        }
+    }
+}
+
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &str {
+        "coingecko"
+    }
+
+    fn quote(&self, currency: &str) -> Result<Quote, PriceError> {
+        let code: String = currency.to_lowercase();
+        let url = format!(
+            "{}?ids=nano&vs_currencies={}&include_last_updated_at=true",
+            self.price_url, code
+        );
+        let body: Vec<u8> = price::fetch(&url, None)?;
+
+        // {"nano": {"eur": 1.23, "last_updated_at": 1536000000}}
+        let prices: HashMap<String, HashMap<String, Value>> = match serde_json::from_slice(&body) {
+            Ok(p) => p,
+            Err(e) => return Err(PriceError::InvalidResponse(e.to_string())),
+        };
+
+        let nano: &HashMap<String, Value> = match prices.get("nano") {
+            Some(n) => n,
+            None => return Err(PriceError::InvalidResponse("no nano entry".to_string())),
+        };
+
+        let price: f64 = match nano.get(&code).and_then(|p| p.as_f64()) {
+            Some(p) => p,
+            None => return Err(PriceError::Unsupported(currency.to_string())),
+        };
+
+        Ok(Quote {
+            currency: currency.to_string(),
+            price,
+            timestamp: match nano.get("last_updated_at").and_then(|t| t.as_i64()) {
+                Some(t) => Utc.timestamp(t, 0),
+                None => Utc::now(),
+            },
+            source: self.name().to_string(),
+        })
+    }
+}
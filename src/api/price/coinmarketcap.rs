@@ -0,0 +1,78 @@
+use api::price::{self, PriceError, PriceProvider, Quote};
+use chrono::{DateTime, Utc};
+use serde_json;
+use std::collections::HashMap;
+
+/// CoinMarketCap id of NANO.
+const NANO_ID: &str = "1567";
+
+/// CoinMarketCap professional API, which needs an API key.
+pub struct CoinMarketCap {
+    quotes_url: String,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct CoinmarketcapInfo {
+    data: HashMap<String, CoinmarketcapData>,
+}
+
+#[derive(Deserialize)]
+struct CoinmarketcapData {
+    //id: u16,
+    //name: String,
+    //symbol: String,
+    //circulating_supply: f64,
+    quote: HashMap<String, CoinmarketcapQuote>,
+}
+
+#[derive(Deserialize)]
+struct CoinmarketcapQuote {
+    price: f64,
+    last_updated: String,
+    // volume_24h: f64,
+    // market_cap: f64,
+    // percent_change_1h: f32,
+    // percent_change_24h: f32,
+    // percent_change_7d: f32
+}
+
+impl CoinMarketCap {
+    pub fn new(quotes_url: &str, api_key: &str) -> CoinMarketCap {
+        CoinMarketCap {
+            quotes_url: quotes_url.to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+}
+
+impl PriceProvider for CoinMarketCap {
+    fn name(&self) -> &str {
+        "coinmarketcap"
+    }
+
+    fn quote(&self, currency: &str) -> Result<Quote, PriceError> {
+        let url = format!("{}?id={}&convert={}", self.quotes_url, NANO_ID, currency);
+        let body: Vec<u8> = price::fetch(&url, Some(("X-CMC_PRO_API_KEY", &self.api_key)))?;
+
+        let info: CoinmarketcapInfo = match serde_json::from_slice(&body) {
+            Ok(i) => i,
+            Err(e) => return Err(PriceError::InvalidResponse(e.to_string())),
+        };
+
+        let quote: &CoinmarketcapQuote =
+            match info.data.get(NANO_ID).and_then(|d| d.quote.get(currency)) {
+                Some(q) => q,
+                None => return Err(PriceError::Unsupported(currency.to_string())),
+            };
+
+        Ok(Quote {
+            currency: currency.to_string(),
+            price: quote.price,
+            timestamp: DateTime::parse_from_rfc3339(&quote.last_updated)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            source: self.name().to_string(),
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use api::price::{PriceError, PriceProvider, Quote};
+use chrono::{DateTime, Utc};
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+
+/// Reads prices from a JSON object of currency codes to prices, such as `{"EUR": 1.5}`. The
+/// quote is as old as the file, so it can stand in for the network in tests or act as a last
+/// resort fallback.
+pub struct FileProvider {
+    path: String,
+}
+
+impl FileProvider {
+    pub fn new(path: &str) -> FileProvider {
+        FileProvider {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl PriceProvider for FileProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn quote(&self, currency: &str) -> Result<Quote, PriceError> {
+        let contents: String = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) => return Err(PriceError::Unavailable(format!("{}: {}", self.path, e))),
+        };
+
+        let prices: HashMap<String, f64> = match serde_json::from_str(&contents) {
+            Ok(p) => p,
+            Err(e) => return Err(PriceError::InvalidResponse(e.to_string())),
+        };
+
+        let price: f64 = match prices.get(currency) {
+            Some(p) => *p,
+            None => return Err(PriceError::Unsupported(currency.to_string())),
+        };
+
+        let timestamp: DateTime<Utc> = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(t) => DateTime::from(t),
+            Err(_) => Utc::now(),
+        };
+
+        Ok(Quote {
+            currency: currency.to_string(),
+            price,
+            timestamp,
+            source: self.name().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn prices_file(name: &str, contents: &str) -> String {
+        let path: String = env::temp_dir()
+            .join(format!("nanobot-prices-{}-{}.json", ::std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+
+        fs::write(&path, contents).expect("prices file");
+
+        path
+    }
+
+    #[test]
+    fn quotes_prices_from_the_file() {
+        let path: String = prices_file("quotes", r#"{"EUR": 1.5, "USD": 2}"#);
+        let provider = FileProvider::new(&path);
+
+        let quote: Quote = provider.quote("EUR").expect("quote");
+
+        assert_eq!((quote.currency.as_str(), quote.price), ("EUR", 1.5));
+        assert_eq!(quote.source, "file");
+        assert_eq!(provider.quote("USD").expect("quote").price, 2.0);
+
+        match provider.quote("GBP") {
+            Err(PriceError::Unsupported(ref c)) => assert_eq!(c, "GBP"),
+            other => panic!("expected unsupported, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_and_malformed_files() {
+        let malformed: String = prices_file("malformed", r#"{"EUR": "cheap"}"#);
+        let missing: String = prices_file("missing", "{}");
+        fs::remove_file(&missing).expect("remove prices file");
+
+        match FileProvider::new(&malformed).quote("EUR") {
+            Err(PriceError::InvalidResponse(_)) => (),
+            other => panic!("expected an invalid response, got {:?}", other),
+        }
+        match FileProvider::new(&missing).quote("EUR") {
+            Err(PriceError::Unavailable(_)) => (),
+            other => panic!("expected unavailable, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+mod coingecko;
+mod coinmarketcap;
+mod file;
+
+pub use self::coingecko::CoinGecko;
+pub use self::coinmarketcap::CoinMarketCap;
+pub use self::file::FileProvider;
+
+use chrono::{DateTime, Utc};
+use config::PriceConfig;
+use futures::{Future, Stream};
+use hyper::{Client, Method, Request, Uri};
+use hyper_tls::HttpsConnector;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Core, Timeout};
+
+/// Fiat currencies every provider is expected to quote.
+pub const SUPPORTED_CURRENCIES: &[&str] = &[
+    "AUD", "BRL", "CAD", "CHF", "CLP", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD", "HUF", "IDR",
+    "ILS", "INR", "JPY", "KRW", "MXN", "MYR", "NOK", "NZD", "PHP", "PKR", "PLN", "RUB", "SEK",
+    "SGD", "THB", "TRY", "TWD", "USD", "ZAR",
+];
+
+/// How long a provider gets to answer before the next one is asked.
+const FETCH_TIMEOUT_SECS: u64 = 10;
+
+/// How long the providers are left alone after none of them could quote a currency.
+const FAILURE_TTL_SECS: u64 = 30;
+
+/// Price of one NANO in `currency`, as reported by `source` at `timestamp`.
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub currency: String,
+    pub price: f64,
+    pub timestamp: DateTime<Utc>,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub enum PriceError {
+    Unsupported(String),
+    Unavailable(String),
+    InvalidResponse(String),
+    NoProvider,
+}
+
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn quote(&self, currency: &str) -> Result<Quote, PriceError>;
+}
+
+/// Asks each provider in turn until one answers, and keeps quotes for `ttl` so a chat burst does
+/// not hit the upstream APIs once per message. When every provider fails they are not asked again
+/// for `FAILURE_TTL_SECS`, and the last quote is served meanwhile, with its own timestamp.
+pub struct PriceFeed {
+    providers: Vec<Box<PriceProvider>>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Quote)>>,
+    failures: Mutex<HashMap<String, Instant>>,
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PriceError::Unsupported(ref c) => write!(f, "{} is not a supported currency", c),
+            PriceError::Unavailable(ref e) => write!(f, "Price source unavailable: {}", e),
+            PriceError::InvalidResponse(ref e) => write!(f, "Unexpected price response: {}", e),
+            PriceError::NoProvider => write!(f, "No price provider configured"),
+        }
+    }
+}
+
+impl Error for PriceError {
+    fn description(&self) -> &str {
+        "price error"
+    }
+}
+
+impl PriceFeed {
+    pub fn new(providers: Vec<Box<PriceProvider>>, ttl: Duration) -> PriceFeed {
+        PriceFeed {
+            providers,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Builds the providers named in `price.providers`, in that order.
+    pub fn from_config(config: &PriceConfig) -> PriceFeed {
+        let mut providers: Vec<Box<PriceProvider>> = Vec::new();
+
+        for name in &config.providers {
+            match name.as_str() {
+                "coingecko" => providers.push(Box::new(CoinGecko::new(&config.coingecko_url))),
+                "coinmarketcap" => match config.coinmarketcap_api_key {
+                    Some(ref key) => providers.push(Box::new(CoinMarketCap::new(
+                        &config.coinmarketcap_url,
+                        key,
+                    ))),
+                    None => println!("Skipping the coinmarketcap price provider, no API key set"),
+                },
+                "file" => {
+                    if let Some(ref path) = config.file {
+                        providers.push(Box::new(FileProvider::new(path)));
+                    }
+                }
+                _ => println!("Unknown price provider {}", name),
+            }
+        }
+
+        PriceFeed::new(providers, Duration::from_secs(config.ttl_secs))
+    }
+}
+
+impl PriceProvider for PriceFeed {
+    fn name(&self) -> &str {
+        "feed"
+    }
+
+    fn quote(&self, currency: &str) -> Result<Quote, PriceError> {
+        let currency: String = currency.to_uppercase();
+
+        if !SUPPORTED_CURRENCIES.contains(&currency.as_str()) {
+            return Err(PriceError::Unsupported(currency));
+        }
+
+        let stale: Option<Quote> = {
+            let cache = self.cache.lock().expect("price cache lock");
+
+            match cache.get(&currency) {
+                Some(&(fetched, ref quote)) if fetched.elapsed() < self.ttl => {
+                    return Ok(quote.clone())
+                }
+                Some(&(_, ref quote)) => Some(quote.clone()),
+                None => None,
+            }
+        };
+
+        let failed: Option<Instant> = self
+            .failures
+            .lock()
+            .expect("price failures lock")
+            .get(&currency)
+            .cloned();
+
+        if let Some(failed) = failed {
+            if failed.elapsed() < Duration::from_secs(FAILURE_TTL_SECS) {
+                return match stale {
+                    Some(quote) => Ok(quote),
+                    None => Err(PriceError::Unavailable(format!(
+                        "no price source could quote {} recently",
+                        currency
+                    ))),
+                };
+            }
+        }
+
+        let mut last_error: PriceError = PriceError::NoProvider;
+
+        for provider in &self.providers {
+            match provider.quote(&currency) {
+                Ok(quote) => {
+                    self.failures
+                        .lock()
+                        .expect("price failures lock")
+                        .remove(&currency);
+                    self.cache
+                        .lock()
+                        .expect("price cache lock")
+                        .insert(currency, (Instant::now(), quote.clone()));
+
+                    return Ok(quote);
+                }
+                Err(e) => {
+                    println!("Price provider {} failed: {}", provider.name(), e);
+                    last_error = e;
+                }
+            }
+        }
+
+        if self.providers.is_empty() {
+            return Err(last_error);
+        }
+
+        self.failures
+            .lock()
+            .expect("price failures lock")
+            .insert(currency, Instant::now());
+
+        match stale {
+            Some(quote) => Ok(quote),
+            None => Err(last_error),
+        }
+    }
+}
+
+/// GETs `url` with an optional extra header and returns the body of a successful response, giving
+/// up after `FETCH_TIMEOUT_SECS`.
+fn fetch(url: &str, header: Option<(&'static str, &str)>) -> Result<Vec<u8>, PriceError> {
+    let uri: Uri = match url.parse() {
+        Ok(u) => u,
+        Err(e) => return Err(PriceError::Unavailable(e.to_string())),
+    };
+
+    let mut core = match Core::new() {
+        Ok(c) => c,
+        Err(e) => return Err(PriceError::Unavailable(e.to_string())),
+    };
+
+    let connector = match HttpsConnector::new(4, &core.handle()) {
+        Ok(c) => c,
+        Err(e) => return Err(PriceError::Unavailable(e.to_string())),
+    };
+
+    let client = Client::configure().connector(connector).build(&core.handle());
+    let mut req = Request::new(Method::Get, uri);
+
+    if let Some((name, value)) = header {
+        req.headers_mut().set_raw(name, value.to_string());
+    }
+
+    let timeout = match Timeout::new(Duration::from_secs(FETCH_TIMEOUT_SECS), &core.handle()) {
+        Ok(t) => t.then(|_| {
+            Err(PriceError::Unavailable(format!(
+                "{} did not respond within {} seconds",
+                url, FETCH_TIMEOUT_SECS
+            )))
+        }),
+        Err(e) => return Err(PriceError::Unavailable(e.to_string())),
+    };
+
+    let work = client
+        .request(req)
+        .and_then(|res| {
+            let status = res.status();
+
+            res.body().concat2().map(move |body| (status, body))
+        })
+        .map_err(|e| PriceError::Unavailable(e.to_string()))
+        .select(timeout)
+        .map(|(response, _)| response)
+        .map_err(|(e, _)| e);
+
+    match core.run(work) {
+        Ok((status, body)) if status.is_success() => Ok(body.to_vec()),
+        Ok((status, _)) => Err(PriceError::Unavailable(format!("{} returned {}", url, status))),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Answers with a fixed price, or fails when it has none, counting every call.
+    struct Fixed {
+        name: &'static str,
+        price: Option<f64>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl PriceProvider for Fixed {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn quote(&self, currency: &str) -> Result<Quote, PriceError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            match self.price {
+                Some(price) => Ok(Quote {
+                    currency: currency.to_string(),
+                    price,
+                    timestamp: Utc::now(),
+                    source: self.name.to_string(),
+                }),
+                None => Err(PriceError::Unavailable(format!("{} is down", self.name))),
+            }
+        }
+    }
+
+    fn fixed(name: &'static str, price: Option<f64>) -> (Box<PriceProvider>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = Fixed {
+            name,
+            price,
+            calls: calls.clone(),
+        };
+
+        (Box::new(provider), calls)
+    }
+
+    #[test]
+    fn falls_back_in_order() {
+        let (down, down_calls) = fixed("down", None);
+        let (first, first_calls) = fixed("first", Some(1.5));
+        let (second, second_calls) = fixed("second", Some(2.5));
+        let feed = PriceFeed::new(vec![down, first, second], Duration::from_secs(0));
+
+        let quote: Quote = feed.quote("eur").expect("quote");
+
+        assert_eq!((quote.source.as_str(), quote.price), ("first", 1.5));
+        assert_eq!(quote.currency, "EUR");
+        assert_eq!(down_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn reports_the_last_failure() {
+        let (first, _) = fixed("first", None);
+        let (second, _) = fixed("second", None);
+        let feed = PriceFeed::new(vec![first, second], Duration::from_secs(0));
+
+        match feed.quote("EUR") {
+            Err(PriceError::Unavailable(ref e)) => assert_eq!(e, "second is down"),
+            other => panic!("expected the second failure, got {:?}", other),
+        }
+        match PriceFeed::new(Vec::new(), Duration::from_secs(0)).quote("EUR") {
+            Err(PriceError::NoProvider) => (),
+            other => panic!("expected no provider, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn caches_quotes_for_the_ttl() {
+        let (cached, cached_calls) = fixed("cached", Some(1.0));
+        let feed = PriceFeed::new(vec![cached], Duration::from_secs(3600));
+
+        feed.quote("EUR").expect("first quote");
+        feed.quote("eur").expect("cached quote");
+        feed.quote("USD").expect("other currency");
+
+        assert_eq!(cached_calls.load(Ordering::SeqCst), 2);
+
+        let (expired, expired_calls) = fixed("expired", Some(1.0));
+        let feed = PriceFeed::new(vec![expired], Duration::from_secs(0));
+
+        feed.quote("EUR").expect("first quote");
+        feed.quote("EUR").expect("refetched quote");
+
+        assert_eq!(expired_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn leaves_failing_providers_alone_for_a_while() {
+        let (down, calls) = fixed("down", None);
+        let feed = PriceFeed::new(vec![down], Duration::from_secs(0));
+
+        assert!(feed.quote("EUR").is_err());
+        assert!(feed.quote("EUR").is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(feed.quote("USD").is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn serves_stale_quotes_while_providers_fail() {
+        let (down, calls) = fixed("down", None);
+        let feed = PriceFeed::new(vec![down], Duration::from_secs(0));
+        let fetched: DateTime<Utc> = Utc::now() - ::chrono::Duration::hours(1);
+
+        feed.cache.lock().unwrap().insert(
+            "EUR".to_string(),
+            (
+                Instant::now(),
+                Quote {
+                    currency: "EUR".to_string(),
+                    price: 1.5,
+                    timestamp: fetched,
+                    source: "earlier".to_string(),
+                },
+            ),
+        );
+
+        for _ in 0..2 {
+            let quote: Quote = feed.quote("EUR").expect("stale quote");
+
+            assert_eq!((quote.source.as_str(), quote.price), ("earlier", 1.5));
+            assert_eq!(quote.timestamp, fetched);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn rejects_unsupported_currencies_before_asking() {
+        let (provider, calls) = fixed("provider", Some(1.0));
+        let feed = PriceFeed::new(vec![provider], Duration::from_secs(0));
+
+        match feed.quote("XYZ") {
+            Err(PriceError::Unsupported(ref c)) => assert_eq!(c, "XYZ"),
+            other => panic!("expected unsupported, got {:?}", other),
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
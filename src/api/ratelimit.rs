@@ -0,0 +1,242 @@
+use config::{BucketConfig, RateLimitConfig};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets are only pruned once there are more than this many, and then only the full ones.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Token buckets keyed by user and by space (a Hangouts room or a Teams conversation). A message
+/// goes through only when both its buckets hold a token.
+pub struct RateLimiter {
+    user: BucketConfig,
+    space: BucketConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &BucketConfig, now: Instant) -> Bucket {
+        Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &BucketConfig, now: Instant) {
+        let elapsed: Duration = now.duration_since(self.updated);
+        let seconds: f64 = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        self.tokens = (self.tokens + seconds * rate(limit)).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+
+    /// Time until the bucket holds a whole token again.
+    fn wait(&self, limit: &BucketConfig) -> Duration {
+        Duration::from_secs(((1.0 - self.tokens) / rate(limit)).ceil().max(1.0) as u64)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            user: config.user,
+            space: config.space,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from both buckets, or tells how long to wait when either is empty.
+    pub fn check(&self, platform: &str, user: &str, space: &str) -> Result<(), Duration> {
+        self.check_at(platform, user, space, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        platform: &str,
+        user: &str,
+        space: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let user_key = format!("{}:user:{}", platform, user);
+        let space_key = format!("{}:space:{}", platform, space);
+        let mut buckets = self.buckets.lock().expect("rate limiter lock");
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            let (user_limit, space_limit) = (self.user, self.space);
+
+            buckets.retain(|key, bucket| {
+                let limit = if key.split(':').nth(1) == Some("user") {
+                    &user_limit
+                } else {
+                    &space_limit
+                };
+
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.burst)
+            });
+        }
+
+        let user_wait: Option<Duration> = {
+            let bucket = buckets
+                .entry(user_key.clone())
+                .or_insert_with(|| Bucket::full(&self.user, now));
+            bucket.refill(&self.user, now);
+
+            if bucket.tokens < 1.0 {
+                Some(bucket.wait(&self.user))
+            } else {
+                None
+            }
+        };
+
+        let space_wait: Option<Duration> = {
+            let bucket = buckets
+                .entry(space_key.clone())
+                .or_insert_with(|| Bucket::full(&self.space, now));
+            bucket.refill(&self.space, now);
+
+            if bucket.tokens < 1.0 {
+                Some(bucket.wait(&self.space))
+            } else {
+                None
+            }
+        };
+
+        match (user_wait, space_wait) {
+            (None, None) => {
+                for key in &[user_key, space_key] {
+                    if let Some(bucket) = buckets.get_mut(key) {
+                        bucket.tokens -= 1.0;
+                    }
+                }
+
+                Ok(())
+            }
+            (Some(a), Some(b)) => Err(a.max(b)),
+            (Some(w), None) | (None, Some(w)) => Err(w),
+        }
+    }
+}
+
+/// Reply for a throttled user, shared by every platform.
+pub fn throttled_message(wait: Duration) -> String {
+    format!(
+        "Whoa, slow down! You are sending commands too quickly, try again in {} seconds",
+        wait.as_secs()
+    )
+}
+
+fn rate(limit: &BucketConfig) -> f64 {
+    f64::from(limit.per_minute) / 60.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(user: (u32, u32), space: (u32, u32)) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            user: BucketConfig {
+                burst: user.0,
+                per_minute: user.1,
+            },
+            space: BucketConfig {
+                burst: space.0,
+                per_minute: space.1,
+            },
+        })
+    }
+
+    fn after(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn exhausts_the_burst() {
+        let limiter = limiter((3, 60), (100, 600));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("teams", "alice", "room", now), Ok(()));
+        }
+
+        assert_eq!(
+            limiter.check_at("teams", "alice", "room", now),
+            Err(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter((1, 6), (100, 600));
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at("teams", "alice", "room", start), Ok(()));
+        assert_eq!(
+            limiter.check_at("teams", "alice", "room", after(start, 1_000)),
+            Err(Duration::from_secs(9))
+        );
+        assert_eq!(
+            limiter.check_at("teams", "alice", "room", after(start, 5_000)),
+            Err(Duration::from_secs(5))
+        );
+        assert_eq!(
+            limiter.check_at("teams", "alice", "room", after(start, 10_000)),
+            Ok(())
+        );
+
+        // A long pause refills no more than the burst.
+        assert_eq!(
+            limiter.check_at("teams", "alice", "room", after(start, 3_600_000)),
+            Ok(())
+        );
+        assert!(limiter
+            .check_at("teams", "alice", "room", after(start, 3_600_000))
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_keys_apart() {
+        let limiter = limiter((1, 1), (2, 1));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("teams", "alice", "room", now), Ok(()));
+        assert!(limiter.check_at("teams", "alice", "room", now).is_err());
+        assert!(limiter.check_at("teams", "alice", "other room", now).is_err());
+        assert_eq!(limiter.check_at("hangouts", "alice", "room", now), Ok(()));
+
+        // The space is shared by everyone in it.
+        assert_eq!(limiter.check_at("teams", "bob", "room", now), Ok(()));
+        assert!(limiter.check_at("teams", "carol", "room", now).is_err());
+        assert_eq!(limiter.check_at("teams", "carol", "other room", now), Ok(()));
+    }
+
+    #[test]
+    fn prunes_full_buckets() {
+        let limiter = limiter((1, 60), (1, 1));
+        let start = Instant::now();
+        let spaces: usize = PRUNE_THRESHOLD / 2 + 1;
+
+        for i in 0..spaces {
+            let name: String = i.to_string();
+            assert_eq!(limiter.check_at("teams", &name, &name, start), Ok(()));
+        }
+
+        assert_eq!(limiter.buckets.lock().unwrap().len(), spaces * 2);
+
+        // A second later the user buckets are full again and go, the spaces are still drained.
+        let later = after(start, 1_000);
+        assert_eq!(limiter.check_at("teams", "alice", "busy room", later), Ok(()));
+
+        let buckets = limiter.buckets.lock().unwrap();
+        let users: Vec<&String> = buckets.keys().filter(|k| k.contains(":user:")).collect();
+
+        assert_eq!(users, vec!["teams:user:alice"]);
+        assert_eq!(buckets.len(), spaces + 2);
+    }
+}
@@ -0,0 +1,108 @@
+/// Chat-agnostic bot reply, rendered by each platform into its own card format.
+pub struct Message {
+    pub text: Option<String>,
+    pub cards: Vec<Card>,
+}
+
+pub struct Card {
+    pub header: String,
+    pub sections: Vec<Section>,
+}
+
+pub struct Section {
+    pub header: Option<String>,
+    pub widgets: Vec<Widget>,
+}
+
+pub enum Widget {
+    KeyValue { label: String, content: String },
+    Image { url: String },
+    QrCode { data: String },
+    Button { text: String, url: String },
+}
+
+impl Message {
+    pub fn text<T: Into<String>>(text: T) -> Message {
+        Message {
+            text: Some(text.into()),
+            cards: vec![],
+        }
+    }
+
+    pub fn card(card: Card) -> Message {
+        Message {
+            text: None,
+            cards: vec![card],
+        }
+    }
+}
+
+impl Card {
+    pub fn new<T: Into<String>>(header: T) -> Card {
+        Card {
+            header: header.into(),
+            sections: vec![],
+        }
+    }
+
+    pub fn section(mut self, section: Section) -> Card {
+        self.sections.push(section);
+        self
+    }
+}
+
+impl Default for Section {
+    fn default() -> Section {
+        Section::new()
+    }
+}
+
+impl Section {
+    pub fn new() -> Section {
+        Section {
+            header: None,
+            widgets: vec![],
+        }
+    }
+
+    pub fn with_header<T: Into<String>>(header: T) -> Section {
+        Section {
+            header: Some(header.into()),
+            widgets: vec![],
+        }
+    }
+
+    pub fn key_value<L: Into<String>, C: Into<String>>(mut self, label: L, content: C) -> Section {
+        self.widgets.push(Widget::KeyValue {
+            label: label.into(),
+            content: content.into(),
+        });
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn image<T: Into<String>>(mut self, url: T) -> Section {
+        self.widgets.push(Widget::Image { url: url.into() });
+        self
+    }
+
+    pub fn qr_code<T: Into<String>>(mut self, data: T) -> Section {
+        self.widgets.push(Widget::QrCode { data: data.into() });
+        self
+    }
+
+    pub fn button<T: Into<String>, U: Into<String>>(mut self, text: T, url: U) -> Section {
+        self.widgets.push(Widget::Button {
+            text: text.into(),
+            url: url.into(),
+        });
+        self
+    }
+}
+
+pub fn qr_code_url(data: &str) -> String {
+    format!(
+        "https://api.qrserver.com/v1/create-qr-code/?size=400x400&data={}",
+        data
+    )
+}
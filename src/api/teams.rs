@@ -1,15 +1,20 @@
+use api::auth::{self, AuthError, KeySet, KeySource};
+use api::commands::{self, Context, Platform, User};
+use api::notify::{self, Notifier};
+use api::response::{self, Message};
 use chrono::{DateTime, Duration, Utc};
-use db;
+use config::TeamsConfig;
+use db::{self, ConversationReference};
 use erased_serde;
 use futures::{Future, Stream};
 use hyper::{client, header, Client, Method, Request};
 use hyper_tls::HttpsConnector;
-use node;
-use rusqlite::Connection;
 use serde_json;
 use std::error::Error;
 use std::sync::Mutex;
+use std::time;
 use tokio_core::reactor::Core;
+use url::form_urlencoded;
 
 #[derive(Deserialize, Debug)]
 pub struct Activity {
@@ -39,6 +44,9 @@ pub struct Activity {
 
     #[serde(default)]
     entities: Vec<Mention>,
+
+    #[serde(rename = "channelData", default)]
+    channel_data: ChannelData,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -55,6 +63,13 @@ struct Conversation {
 
     #[serde(default)]
     name: String,
+
+    #[serde(
+        rename = "conversationType",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    conversation_type: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -65,6 +80,17 @@ struct Recipient {
     name: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ChannelData {
+    #[serde(default)]
+    tenant: Option<Tenant>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Tenant {
+    id: String,
+}
+
 #[derive(Deserialize, Debug)]
 struct Mention {
     #[serde(rename = "type")]
@@ -148,6 +174,30 @@ struct TeamsResponseAdaptive {
 //     value: String
 // }
 
+/// Message started by the bot rather than sent in reply to an activity.
+#[derive(Serialize)]
+struct ProactiveActivity {
+    #[serde(rename = "type")]
+    activity_type: String,
+
+    text: Option<String>,
+    attachments: Vec<AttachmentAdaptive>,
+}
+
+#[derive(Serialize)]
+struct ConversationParameters {
+    bot: From,
+    members: Vec<Recipient>,
+
+    #[serde(rename = "channelData")]
+    channel_data: ChannelData,
+}
+
+#[derive(Deserialize)]
+struct ConversationResource {
+    id: String,
+}
+
 #[derive(Serialize)]
 struct AttachmentAdaptive {
     #[serde(rename = "contentType")]
@@ -164,6 +214,9 @@ struct AdaptiveCard {
     card_type: String,
 
     body: Vec<Box<CardBody>>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<OpenUrlAction>,
 }
 
 #[derive(Serialize)]
@@ -213,6 +266,15 @@ struct ImageBlock {
     horizontal_alignment: Option<String>,
 }
 
+#[derive(Serialize)]
+struct OpenUrlAction {
+    #[serde(rename = "type")]
+    action_type: String,
+
+    title: String,
+    url: String,
+}
+
 trait CardBody: erased_serde::Serialize {}
 impl CardBody for TextBlock {}
 impl CardBody for ColumnSet {}
@@ -225,6 +287,36 @@ pub struct TeamsToken {
     pub expire_date: DateTime<Utc>,
 }
 
+/// Messages users through the Bot Framework connector, opening a personal conversation when the
+/// stored reference came from a channel or group chat.
+pub struct TeamsNotifier {
+    config: TeamsConfig,
+    token: Mutex<TeamsToken>,
+}
+
+/// Signing keys of the Bot Framework connector, kept apart from other key sets in managed state.
+pub struct BotFrameworkKeys(pub KeySet);
+
+#[derive(Deserialize)]
+struct BotFrameworkClaims {
+    #[serde(rename = "serviceurl", default)]
+    service_url: String,
+
+    /// Older connectors spell the claim in camel case.
+    #[serde(rename = "serviceUrl", default)]
+    legacy_service_url: String,
+}
+
+impl BotFrameworkClaims {
+    fn service_url(&self) -> &str {
+        if self.service_url.is_empty() {
+            &self.legacy_service_url
+        } else {
+            &self.service_url
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct TokenResponse {
     token_type: String,
@@ -243,16 +335,177 @@ struct TokenError {
     correlation_id: String,
 }
 
+impl BotFrameworkKeys {
+    pub fn new(teams_config: &TeamsConfig) -> BotFrameworkKeys {
+        let source = match teams_config.openid_keys_file {
+            Some(ref path) => KeySource::File(path.to_owned()),
+            None => KeySource::Url(teams_config.openid_keys_url.to_owned()),
+        };
+
+        BotFrameworkKeys(KeySet::new(source, time::Duration::from_secs(24 * 60 * 60)))
+    }
+}
+
+impl TeamsNotifier {
+    pub fn new(teams_config: &TeamsConfig) -> TeamsNotifier {
+        TeamsNotifier {
+            config: teams_config.clone(),
+            token: Mutex::new(TeamsToken {
+                value: String::new(),
+                expire_date: Utc::now(),
+            }),
+        }
+    }
+
+    fn open_conversation(
+        &self,
+        reference: &ConversationReference,
+        token: &str,
+    ) -> Result<String, Box<Error>> {
+        let parameters = ConversationParameters {
+            bot: From {
+                id: reference.bot_id.to_owned(),
+                name: String::new(),
+            },
+            members: vec![Recipient {
+                id: reference.external_id.to_owned(),
+                name: String::new(),
+            }],
+            channel_data: ChannelData {
+                tenant: if reference.tenant_id.is_empty() {
+                    None
+                } else {
+                    Some(Tenant {
+                        id: reference.tenant_id.to_owned(),
+                    })
+                },
+            },
+        };
+
+        let conversation: ConversationResource = serde_json::from_slice(&notify::post(
+            &format!("{}v3/conversations", reference.service_url),
+            header::ContentType::json(),
+            serde_json::to_string(&parameters)?,
+            Some(token),
+        )?)?;
+
+        Ok(conversation.id)
+    }
+}
+
+impl Notifier for TeamsNotifier {
+    fn platform(&self) -> &str {
+        "teams"
+    }
+
+    fn notify(
+        &self,
+        reference: &ConversationReference,
+        message: Message,
+    ) -> Result<(), Box<Error>> {
+        let token: String = get_bearer_token(&self.token, &self.config)?;
+        let conversation: String = if reference.conversation.is_empty() {
+            self.open_conversation(reference, &token)?
+        } else {
+            reference.conversation.to_owned()
+        };
+
+        let activity = ProactiveActivity {
+            activity_type: "message".to_string(),
+            attachments: message.cards.iter().map(render_card).collect(),
+            text: message.text,
+        };
+
+        notify::post(
+            &format!(
+                "{}v3/conversations/{}/activities",
+                reference.service_url, conversation
+            ),
+            header::ContentType::json(),
+            serde_json::to_string(&activity)?,
+            Some(&token),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Checks that `token` was issued by the Bot Framework for this bot and for the service the
+/// activity claims to come from.
+pub fn authenticate(
+    token: &str,
+    activity: &Activity,
+    keys: &BotFrameworkKeys,
+    teams_config: &TeamsConfig,
+) -> Result<(), AuthError> {
+    let (claims, key): (BotFrameworkClaims, auth::Jwk) = auth::verify(
+        token,
+        &keys.0,
+        &["https://api.botframework.com"],
+        &teams_config.app_id,
+    )?;
+
+    if claims.service_url() != activity.service_url {
+        return Err(AuthError::Claim("serviceurl"));
+    }
+
+    if !key.endorsements.is_empty() && !key.endorsements.contains(&activity.channel_id) {
+        return Err(AuthError::Claim("channelId"));
+    }
+
+    Ok(())
+}
+
+impl Activity {
+    pub fn is_message(&self) -> bool {
+        self.activity_type == "message"
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.from.id
+    }
+
+    pub fn space_id(&self) -> &str {
+        &self.conversation.id
+    }
+}
+
 pub fn handle_message(
     activity: Activity,
     bearer_token: &Mutex<TeamsToken>,
-    db_conn: &Mutex<Connection>,
+    teams_config: &TeamsConfig,
+    ctx: &Context,
 ) -> Result<(), Box<Error>> {
-    let token: String = get_bearer_token(bearer_token)?;
-    let account: node::Account = db::get_account_teams(db_conn, &activity.from.id)?;
-    let balance: node::Balance = node::get_balance(account.account)?;
+    if !activity.is_message() {
+        return Ok(());
+    }
+
+    remember_conversation(ctx, &activity);
 
-    //auajFVRL55[[pylEWN522*!
+    let user = User {
+        platform: Platform::Teams,
+        id: activity.from.id.to_owned(),
+        display_name: activity.from.name.to_owned(),
+    };
+
+    let (text, mentions) = resolve_mentions(&activity);
+    let direct: bool = activity.conversation.conversation_type == "personal";
+    let message: Message = commands::handle(ctx, &text, &user, &mentions, direct);
+
+    reply(activity, message, bearer_token, teams_config)
+}
+
+/// Posts `message` to the conversation as a reply to `activity`.
+pub fn reply(
+    activity: Activity,
+    message: Message,
+    bearer_token: &Mutex<TeamsToken>,
+    teams_config: &TeamsConfig,
+) -> Result<(), Box<Error>> {
+    let text: Option<String> = message.text;
+    let attachments: Vec<AttachmentAdaptive> = message.cards.iter().map(render_card).collect();
+
+    let token: String = get_bearer_token(bearer_token, teams_config)?;
 
     let mut core = Core::new()?;
     let client = Client::configure()
@@ -265,14 +518,6 @@ pub fn handle_message(
 
     let mut req = Request::new(Method::Post, uri);
 
-    println!("creating core and request");
-
-    let attachments: Vec<AttachmentAdaptive> = match activity.text.trim() {
-        "!balance" => vec![get_balance_card(&balance)],
-        "!tip" => vec![get_deposit_card()],
-        _ => return Ok(()),
-    };
-
     let teams_response = TeamsResponseAdaptive {
         response_type: "message".to_string(),
         from: From {
@@ -282,20 +527,19 @@ pub fn handle_message(
         conversation: Conversation {
             id: activity.conversation.id,
             name: activity.conversation.name,
+            conversation_type: activity.conversation.conversation_type,
         },
         recipient: Recipient {
             id: activity.from.id,
             name: activity.from.name,
         },
         attachments: attachments,
-        text: None,
+        text: text,
         reply_to_id: activity.id,
     };
 
     let json = serde_json::to_string(&teams_response)?;
 
-    println!("creating teams response");
-
     req.headers_mut().set(header::ContentType::json());
     req.headers_mut()
         .set(header::ContentLength(json.len() as u64));
@@ -303,20 +547,69 @@ pub fn handle_message(
         .set(header::Authorization(header::Bearer { token: token }));
     req.set_body(json);
 
-    println!("setting headers");
-
     let post = client.request(req).and_then(|res| res.body().concat2());
 
-    println!("creating post");
-
     core.run(post)?;
 
-    println!("done");
-
     Ok(())
 }
 
-fn get_bearer_token(teams_token: &Mutex<TeamsToken>) -> Result<String, Box<Error>> {
+/// Keeps what is needed to message the sender later. Only personal conversations are kept as they
+/// are, anything else is replaced by a personal conversation when notifying.
+fn remember_conversation(ctx: &Context, activity: &Activity) {
+    let reference = ConversationReference {
+        platform: Platform::Teams.as_str().to_string(),
+        external_id: activity.from.id.to_owned(),
+        conversation: if activity.conversation.conversation_type == "personal" {
+            activity.conversation.id.to_owned()
+        } else {
+            String::new()
+        },
+        service_url: activity.service_url.to_owned(),
+        bot_id: activity.recipient.id.to_owned(),
+        tenant_id: match activity.channel_data.tenant {
+            Some(ref t) => t.id.to_owned(),
+            None => String::new(),
+        },
+    };
+
+    if let Err(e) = db::save_conversation(ctx.db_conn, &reference, &Utc::now().to_rfc3339()) {
+        println!("Could not store the conversation of {}: {}", activity.from.id, e);
+    }
+}
+
+/// Drops the mention of the bot itself and replaces every other `<at>name</at>` with the user id
+/// it stands for.
+fn resolve_mentions(activity: &Activity) -> (String, Vec<User>) {
+    let mut text: String = activity.text.to_owned();
+    let mut mentions: Vec<User> = Vec::new();
+
+    for mention in activity.entities.iter().filter(|e| e.activity_type == "mention") {
+        if mention.text.is_empty() {
+            continue;
+        }
+
+        if mention.mentioned.id == activity.recipient.id {
+            text = text.replace(&mention.text, "");
+            continue;
+        }
+
+        text = text.replace(&mention.text, &mention.mentioned.id);
+
+        mentions.push(User {
+            platform: Platform::Teams,
+            id: mention.mentioned.id.to_owned(),
+            display_name: mention.mentioned.name.to_owned(),
+        });
+    }
+
+    (text.trim().to_string(), mentions)
+}
+
+fn get_bearer_token(
+    teams_token: &Mutex<TeamsToken>,
+    teams_config: &TeamsConfig,
+) -> Result<String, Box<Error>> {
     let mut current_token = teams_token.lock().expect("Could not lock mutex");
 
     if current_token.expire_date >= Utc::now() {
@@ -325,10 +618,17 @@ fn get_bearer_token(teams_token: &Mutex<TeamsToken>) -> Result<String, Box<Error
 
     let mut core = Core::new()?;
     let client = get_https_client(&core)?;
-    let uri = "https://login.microsoftonline.com/botframework.com/oauth2/v2.0/token".parse()?;
+    let uri = teams_config.token_url.parse()?;
     let mut req = Request::new(Method::Post, uri);
-    let body = "grant_type=client_credentials&client_id=88768615-5b58-4cd2-a6d8-a51bbec10126&client_secret=auajFVRL55[[pylEWN522*!&scope=https%3A%2F%2Fapi.botframework.com%2F.default";
+    let body = form_urlencoded::Serializer::new(String::new())
+        .append_pair("grant_type", "client_credentials")
+        .append_pair("client_id", &teams_config.app_id)
+        .append_pair("client_secret", &teams_config.app_password)
+        .append_pair("scope", "https://api.botframework.com/.default")
+        .finish();
 
+    req.headers_mut()
+        .set(header::ContentType::form_url_encoded());
     req.headers_mut()
         .set(header::ContentLength(body.len() as u64));
     req.set_body(body);
@@ -355,239 +655,187 @@ fn get_https_client(
     Ok(client)
 }
 
-fn get_deposit_card() -> AttachmentAdaptive {
-    AttachmentAdaptive {
-        content_type: "application/vnd.microsoft.card.adaptive".to_string(),
-        content: AdaptiveCard {
-            card_type: "AdaptiveCard".to_string(),
-            version: "1.0".to_string(),
-            body: vec![
-                Box::new(TextBlock {
-                    body_type: "TextBlock".to_string(),
-                    text: "Deposit NANO".to_string(),
-                    weight: Some("bolder".to_string()),
-                    color: None,
-                    size: None,
+fn render_card(card: &response::Card) -> AttachmentAdaptive {
+    let mut body: Vec<Box<CardBody>> = vec![Box::new(TextBlock {
+        body_type: "TextBlock".to_string(),
+        text: card.header.to_owned(),
+        weight: Some("bolder".to_string()),
+        color: None,
+        size: None,
+        spacing: None,
+        horizontal_alignment: None,
+    })];
+    let mut actions: Vec<OpenUrlAction> = vec![];
+
+    for section in &card.sections {
+        if let Some(ref header) = section.header {
+            body.push(Box::new(TextBlock {
+                body_type: "TextBlock".to_string(),
+                text: header.to_owned(),
+                weight: None,
+                color: None,
+                size: None,
+                spacing: Some("medium".to_string()),
+                horizontal_alignment: None,
+            }));
+        }
+
+        for widget in &section.widgets {
+            match *widget {
+                response::Widget::KeyValue {
+                    ref label,
+                    ref content,
+                } => body.push(Box::new(key_value_column_set(label, content))),
+                response::Widget::Image { ref url } => body.push(Box::new(ImageBlock {
+                    body_type: "Image".to_string(),
+                    url: url.to_owned(),
+                    size: Some("small".to_string()),
                     spacing: None,
                     horizontal_alignment: None,
-                }),
-                Box::new(ColumnSet {
-                    body_type: "ColumnSet".to_string(),
-                    separator: true,
-                    spacing: None,
-                    columns: vec![
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "Sender".to_string(),
-                                    weight: None,
-                                    color: None,
-                                    size: None,
-                                    spacing: None,
-                                    horizontal_alignment: None,
-                                }),
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "{sender_id}".to_string(),
-                                    weight: None,
-                                    color: Some("accent".to_string()),
-                                    size: Some("large".to_string()),
-                                    spacing: Some("none".to_string()),
-                                    horizontal_alignment: None,
-                                }),
-                            ],
-                        },
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "auto".to_string(),
-                            items: vec![
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "".to_string(),
-                                    weight: None,
-                                    color: None,
-                                    size: None,
-                                    spacing: None,
-                                    horizontal_alignment: None,
-                                }),
-                                Box::new(ImageBlock {
-                                    body_type: "Image".to_string(),
-                                    url: "https://i.imgur.com/6J8tqcM.png".to_string(),
-                                    size: Some("small".to_string()),
-                                    spacing: Some("none".to_string()),
-                                    horizontal_alignment: None,
-                                }),
-                            ],
-                        },
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "Receiver".to_string(),
-                                    weight: None,
-                                    color: None,
-                                    size: None,
-                                    spacing: None,
-                                    horizontal_alignment: Some("right".to_string()),
-                                }),
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "{receiver_id}".to_string(),
-                                    weight: None,
-                                    color: Some("accent".to_string()),
-                                    size: Some("large".to_string()),
-                                    spacing: Some("none".to_string()),
-                                    horizontal_alignment: Some("right".to_string()),
-                                }),
-                            ],
-                        },
-                    ],
-                }),
-                Box::new(ColumnSet {
-                    body_type: "ColumnSet".to_string(),
-                    separator: true,
+                })),
+                response::Widget::QrCode { ref data } => body.push(Box::new(ImageBlock {
+                    body_type: "Image".to_string(),
+                    url: response::qr_code_url(data),
+                    size: None,
                     spacing: None,
-                    columns: vec![Column {
-                        body_type: "Column".to_string(),
-                        width: "auto".to_string(),
-                        items: vec![Box::new(ImageBlock {
-                            body_type: "Image".to_string(),
-                            url: "https://api.qrserver.com/v1/create-qr-code/?size=400x400&data={}"
-                                .to_string(),
-                            size: None,
-                            spacing: None,
-                            horizontal_alignment: Some("center".to_string()),
-                        })],
-                    }],
-                }),
-                Box::new(ColumnSet {
-                    body_type: "ColumnSet".to_string(),
-                    separator: true,
-                    spacing: Some("medium".to_string()),
-                    columns: vec![
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![Box::new(TextBlock {
-                                body_type: "TextBlock".to_string(),
-                                text: "Total".to_string(),
-                                weight: None,
-                                color: None,
-                                size: Some("medium".to_string()),
-                                spacing: None,
-                                horizontal_alignment: None,
-                            })],
-                        },
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![Box::new(TextBlock {
-                                body_type: "TextBlock".to_string(),
-                                text: "{amount}".to_string(),
-                                weight: Some("bolder".to_string()),
-                                color: None,
-                                size: Some("medium".to_string()),
-                                spacing: None,
-                                horizontal_alignment: Some("right".to_string()),
-                            })],
-                        },
-                    ],
+                    horizontal_alignment: Some("center".to_string()),
+                })),
+                response::Widget::Button { ref text, ref url } => actions.push(OpenUrlAction {
+                    action_type: "Action.OpenUrl".to_string(),
+                    title: text.to_owned(),
+                    url: url.to_owned(),
                 }),
-            ],
-        },
+            }
+        }
     }
-}
 
-fn get_balance_card(balance: &node::Balance) -> AttachmentAdaptive {
     AttachmentAdaptive {
         content_type: "application/vnd.microsoft.card.adaptive".to_string(),
         content: AdaptiveCard {
             card_type: "AdaptiveCard".to_string(),
             version: "1.0".to_string(),
-            body: vec![
-                Box::new(TextBlock {
+            body,
+            actions,
+        },
+    }
+}
+
+fn key_value_column_set(label: &str, content: &str) -> ColumnSet {
+    ColumnSet {
+        body_type: "ColumnSet".to_string(),
+        separator: true,
+        spacing: None,
+        columns: vec![
+            Column {
+                body_type: "Column".to_string(),
+                width: "1".to_string(),
+                items: vec![Box::new(TextBlock {
                     body_type: "TextBlock".to_string(),
-                    text: "NANO Balance".to_string(),
-                    weight: Some("bolder".to_string()),
+                    text: label.to_string(),
+                    weight: None,
                     color: None,
                     size: None,
                     spacing: None,
                     horizontal_alignment: None,
-                }),
-                Box::new(ColumnSet {
-                    body_type: "ColumnSet".to_string(),
-                    separator: true,
+                })],
+            },
+            Column {
+                body_type: "Column".to_string(),
+                width: "2".to_string(),
+                items: vec![Box::new(TextBlock {
+                    body_type: "TextBlock".to_string(),
+                    text: content.to_string(),
+                    weight: None,
+                    color: Some("accent".to_string()),
+                    size: None,
                     spacing: None,
-                    columns: vec![
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "Current".to_string(),
-                                    weight: None,
-                                    color: None,
-                                    size: None,
-                                    spacing: None,
-                                    horizontal_alignment: None,
-                                }),
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: balance.balance.to_string(),
-                                    weight: None,
-                                    color: Some("accent".to_string()),
-                                    size: Some("large".to_string()),
-                                    spacing: Some("none".to_string()),
-                                    horizontal_alignment: None,
-                                }),
-                            ],
-                        },
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "auto".to_string(),
-                            items: vec![Box::new(TextBlock {
-                                body_type: "TextBlock".to_string(),
-                                text: "".to_string(),
-                                weight: None,
-                                color: None,
-                                size: None,
-                                spacing: None,
-                                horizontal_alignment: None,
-                            })],
-                        },
-                        Column {
-                            body_type: "Column".to_string(),
-                            width: "1".to_string(),
-                            items: vec![
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: "Pending".to_string(),
-                                    weight: None,
-                                    color: None,
-                                    size: None,
-                                    spacing: None,
-                                    horizontal_alignment: Some("right".to_string()),
-                                }),
-                                Box::new(TextBlock {
-                                    body_type: "TextBlock".to_string(),
-                                    text: balance.pending.to_string(),
-                                    weight: None,
-                                    color: Some("accent".to_string()),
-                                    size: Some("large".to_string()),
-                                    spacing: Some("none".to_string()),
-                                    horizontal_alignment: Some("right".to_string()),
-                                }),
-                            ],
-                        },
-                    ],
-                }),
-            ],
-        },
+                    horizontal_alignment: Some("right".to_string()),
+                })],
+            },
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::auth::tests::{self as keys, AUDIENCE, KID};
+
+    const SERVICE_URL: &str = "https://smba.trafficmanager.net/emea/";
+
+    fn activity(service_url: &str) -> Activity {
+        serde_json::from_value(json!({
+            "type": "message",
+            "id": "1",
+            "timestamp": "2018-06-01T12:00:00Z",
+            "serviceUrl": service_url,
+            "channelId": "msteams",
+        })).expect("activity")
+    }
+
+    fn teams_config() -> TeamsConfig {
+        serde_json::from_value(json!({ "app_id": AUDIENCE, "app_password": "secret" }))
+            .expect("teams config")
+    }
+
+    fn authenticate_with(claims: serde_json::Value, service_url: &str) -> Result<(), String> {
+        let (key_set, _) = keys::key_set(&[KID]);
+        let token: String = keys::token(KID, &claims);
+
+        authenticate(
+            &token,
+            &activity(service_url),
+            &BotFrameworkKeys(key_set),
+            &teams_config(),
+        ).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn renders_cards_as_adaptive_cards() {
+        let card = response::Card::new("Balance").section(
+            response::Section::new()
+                .image("https://example.com/logo.png")
+                .button("Explorer", "https://example.com/account"),
+        );
+
+        let rendered = serde_json::to_value(render_card(&card)).unwrap();
+        let content = &rendered["content"];
+
+        assert_eq!(rendered["contentType"], "application/vnd.microsoft.card.adaptive");
+        assert_eq!(content["type"], "AdaptiveCard");
+        assert_eq!(content["body"][0]["text"], "Balance");
+        assert_eq!(
+            content["body"][1],
+            json!({
+                "type": "Image",
+                "url": "https://example.com/logo.png",
+                "size": "small",
+                "spacing": null,
+                "horizontalAlignment": null,
+            })
+        );
+        assert_eq!(
+            content["actions"],
+            json!([{
+                "type": "Action.OpenUrl",
+                "title": "Explorer",
+                "url": "https://example.com/account",
+            }])
+        );
+    }
+
+    #[test]
+    fn checks_the_service_url_claim() {
+        let issuer = "https://api.botframework.com";
+
+        let current = keys::claims(issuer, AUDIENCE, json!({ "serviceurl": SERVICE_URL }));
+        let legacy = keys::claims(issuer, AUDIENCE, json!({ "serviceUrl": SERVICE_URL }));
+
+        assert_eq!(authenticate_with(current.clone(), SERVICE_URL), Ok(()));
+        assert_eq!(authenticate_with(legacy, SERVICE_URL), Ok(()));
+        assert_eq!(
+            authenticate_with(current, "https://evil.example.com/"),
+            Err(AuthError::Claim("serviceurl").to_string())
+        );
     }
 }
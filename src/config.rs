@@ -0,0 +1,837 @@
+use api::price::SUPPORTED_CURRENCIES;
+use hyper::Uri;
+use node::NanoAddress;
+use rocket;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use toml;
+
+/// Bot settings, read from the `[<env>.nanobot]` table of `Rocket.toml` or from the TOML file
+/// named by `NANOBOT_CONFIG`, with `NANOBOT_*` environment variables taking precedence.
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub node: NodeConfig,
+
+    #[serde(default)]
+    pub database: DatabaseConfig,
+
+    #[serde(default)]
+    pub hangouts: Option<HangoutsConfig>,
+
+    #[serde(default)]
+    pub teams: Option<TeamsConfig>,
+
+    #[serde(default)]
+    pub price: PriceConfig,
+
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    #[serde(default)]
+    pub receiver: ReceiverConfig,
+
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+
+    #[serde(default)]
+    pub custody: CustodyConfig,
+}
+
+/// With `signing = "wallet"` user keys are added to node wallets and the node signs; with
+/// `"local"` the bot signs state blocks itself and only needs `work_generate` and `process`.
+#[derive(Deserialize, Clone)]
+pub struct NodeConfig {
+    #[serde(default = "default_node_url")]
+    pub url: String,
+
+    #[serde(default = "default_node_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Node websocket for confirmation events, such as `ws://127.0.0.1:7078`.
+    #[serde(default)]
+    pub websocket_url: Option<String>,
+
+    #[serde(default = "default_node_signing")]
+    pub signing: String,
+
+    /// Representative of accounts opened with local signing.
+    #[serde(default)]
+    pub representative: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DatabaseConfig {
+    #[serde(default = "default_database_path")]
+    pub path: String,
+}
+
+/// Either value enables `/hangouts`: `verification_token` checks the `token` field of each event,
+/// `project_number` checks the bearer token Google Chat signs requests with.
+#[derive(Deserialize)]
+pub struct HangoutsConfig {
+    #[serde(default)]
+    pub verification_token: Option<String>,
+
+    #[serde(default)]
+    pub project_number: Option<String>,
+
+    #[serde(default = "default_hangouts_openid_keys_url")]
+    pub openid_keys_url: String,
+
+    #[serde(default)]
+    pub openid_keys_file: Option<String>,
+
+    /// Service account key file (JSON) that lets the bot post messages on its own, needed for
+    /// deposit notifications.
+    #[serde(default)]
+    pub service_account_file: Option<String>,
+
+    #[serde(default = "default_hangouts_chat_api_url")]
+    pub chat_api_url: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TeamsConfig {
+    pub app_id: String,
+    pub app_password: String,
+
+    #[serde(default = "default_teams_token_url")]
+    pub token_url: String,
+
+    #[serde(default = "default_teams_openid_keys_url")]
+    pub openid_keys_url: String,
+
+    /// Reads the signing keys from a local JWKS file instead of `openid_keys_url`.
+    #[serde(default)]
+    pub openid_keys_file: Option<String>,
+}
+
+/// Prices are asked from `providers` in order, one of `coingecko`, `coinmarketcap` and `file`.
+#[derive(Deserialize)]
+pub struct PriceConfig {
+    #[serde(default = "default_price_providers")]
+    pub providers: Vec<String>,
+
+    #[serde(default = "default_coingecko_url")]
+    pub coingecko_url: String,
+
+    #[serde(default = "default_price_url")]
+    pub coinmarketcap_url: String,
+
+    #[serde(default)]
+    pub coinmarketcap_api_key: Option<String>,
+
+    /// JSON file of currency codes to prices, for the `file` provider.
+    #[serde(default)]
+    pub file: Option<String>,
+
+    #[serde(default = "default_price_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// Currency shown to users who have not picked one with `!currency`.
+    #[serde(default = "default_currency")]
+    pub default_currency: String,
+}
+
+/// Background receiving of pending blocks, at most `batch` blocks per account and pass.
+#[derive(Deserialize)]
+pub struct ReceiverConfig {
+    #[serde(default = "default_receiver_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_receiver_interval_secs")]
+    pub interval_secs: u64,
+
+    #[serde(default = "default_receiver_batch")]
+    pub batch: u32,
+}
+
+/// Messages sent to users when a deposit reaches their account. Confirmations come from
+/// `node.websocket_url` while it is connected, otherwise `account_history` is polled every
+/// `poll_interval_secs`. With `mock` the messages are only logged.
+#[derive(Deserialize)]
+pub struct NotificationConfig {
+    #[serde(default = "default_notifications_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_notification_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+
+    #[serde(default = "default_notification_history_count")]
+    pub history_count: u32,
+
+    #[serde(default)]
+    pub mock: bool,
+}
+
+/// With `enabled`, balances live in a ledger in the database: deposits are swept into a hot
+/// wallet by the receiver, tips only move ledger balances and withdrawals are sent from the hot
+/// wallet.
+#[derive(Deserialize, Default)]
+pub struct CustodyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Token buckets applied to every incoming message, per user and per space.
+#[derive(Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_user_limit")]
+    pub user: BucketConfig,
+
+    #[serde(default = "default_space_limit")]
+    pub space: BucketConfig,
+}
+
+/// Up to `burst` messages at once, refilled at `per_minute`.
+#[derive(Deserialize, Clone, Copy)]
+pub struct BucketConfig {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        NodeConfig {
+            url: default_node_url(),
+            timeout_secs: default_node_timeout_secs(),
+            websocket_url: None,
+            signing: default_node_signing(),
+            representative: None,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            path: default_database_path(),
+        }
+    }
+}
+
+impl Default for PriceConfig {
+    fn default() -> PriceConfig {
+        PriceConfig {
+            providers: default_price_providers(),
+            coingecko_url: default_coingecko_url(),
+            coinmarketcap_url: default_price_url(),
+            coinmarketcap_api_key: None,
+            file: None,
+            ttl_secs: default_price_ttl_secs(),
+            default_currency: default_currency(),
+        }
+    }
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> ReceiverConfig {
+        ReceiverConfig {
+            enabled: default_receiver_enabled(),
+            interval_secs: default_receiver_interval_secs(),
+            batch: default_receiver_batch(),
+        }
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> NotificationConfig {
+        NotificationConfig {
+            enabled: default_notifications_enabled(),
+            poll_interval_secs: default_notification_poll_interval_secs(),
+            history_count: default_notification_history_count(),
+            mock: false,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            user: default_user_limit(),
+            space: default_space_limit(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref e) => write!(f, "Could not read config {}: {}", path, e),
+            ConfigError::Parse(ref e) => write!(f, "Could not parse config: {}", e),
+            ConfigError::Invalid(ref e) => write!(f, "Invalid config: {}", e),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        "configuration error"
+    }
+}
+
+impl NodeConfig {
+    pub fn local_signing(&self) -> bool {
+        self.signing == "local"
+    }
+}
+
+impl Config {
+    pub fn load(rocket_config: &rocket::Config) -> Result<Config, ConfigError> {
+        let mut config: Config = match env::var("NANOBOT_CONFIG") {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) => match rocket_config.get_table("nanobot") {
+                Ok(table) => match toml::Value::Table(table.clone()).try_into() {
+                    Ok(c) => c,
+                    Err(e) => return Err(ConfigError::Parse(e.to_string())),
+                },
+                Err(_) => Config::default(),
+            },
+        };
+
+        config.apply_env();
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let contents: String = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return Err(ConfigError::Io(path.to_string(), e)),
+        };
+
+        match toml::from_str(&contents) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(ConfigError::Parse(e.to_string())),
+        }
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(url) = env::var("NANOBOT_NODE_URL") {
+            self.node.url = url;
+        }
+
+        if let Some(timeout) = env::var("NANOBOT_NODE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|t| t.parse().ok())
+        {
+            self.node.timeout_secs = timeout;
+        }
+
+        if let Ok(url) = env::var("NANOBOT_NODE_WEBSOCKET_URL") {
+            self.node.websocket_url = Some(url);
+        }
+
+        if let Ok(signing) = env::var("NANOBOT_NODE_SIGNING") {
+            self.node.signing = signing;
+        }
+
+        if let Ok(representative) = env::var("NANOBOT_NODE_REPRESENTATIVE") {
+            self.node.representative = Some(representative);
+        }
+
+        if let Ok(path) = env::var("NANOBOT_DATABASE_PATH") {
+            self.database.path = path;
+        }
+
+        if let Ok(url) = env::var("NANOBOT_PRICE_URL") {
+            self.price.coinmarketcap_url = url;
+        }
+
+        if let Ok(key) = env::var("NANOBOT_COINMARKETCAP_API_KEY") {
+            self.price.coinmarketcap_api_key = Some(key);
+        }
+
+        if let Ok(path) = env::var("NANOBOT_PRICE_FILE") {
+            self.price.file = Some(path);
+        }
+
+        if let Ok(providers) = env::var("NANOBOT_PRICE_PROVIDERS") {
+            self.price.providers = providers
+                .split(',')
+                .map(|p| p.trim().to_lowercase())
+                .filter(|p| !p.is_empty())
+                .collect();
+        }
+
+        if let Ok(currency) = env::var("NANOBOT_PRICE_CURRENCY") {
+            self.price.default_currency = currency;
+        }
+
+        self.price.default_currency = self.price.default_currency.trim().to_uppercase();
+        self.node.signing = self.node.signing.trim().to_lowercase();
+
+        let verification_token: Option<String> = env::var("NANOBOT_HANGOUTS_TOKEN").ok();
+        let project_number: Option<String> = env::var("NANOBOT_HANGOUTS_PROJECT_NUMBER").ok();
+
+        if verification_token.is_some() || project_number.is_some() {
+            let hangouts = self.hangouts.get_or_insert_with(|| HangoutsConfig {
+                verification_token: None,
+                project_number: None,
+                openid_keys_url: default_hangouts_openid_keys_url(),
+                openid_keys_file: None,
+                service_account_file: None,
+                chat_api_url: default_hangouts_chat_api_url(),
+            });
+
+            if verification_token.is_some() {
+                hangouts.verification_token = verification_token;
+            }
+
+            if project_number.is_some() {
+                hangouts.project_number = project_number;
+            }
+        }
+
+        if let (Ok(app_id), Ok(app_password)) = (
+            env::var("NANOBOT_TEAMS_APP_ID"),
+            env::var("NANOBOT_TEAMS_APP_PASSWORD"),
+        ) {
+            match self.teams {
+                Some(ref mut t) => {
+                    t.app_id = app_id;
+                    t.app_password = app_password;
+                }
+                None => {
+                    self.teams = Some(TeamsConfig {
+                        app_id,
+                        app_password,
+                        token_url: default_teams_token_url(),
+                        openid_keys_url: default_teams_openid_keys_url(),
+                        openid_keys_file: None,
+                    })
+                }
+            }
+        }
+
+        if let Ok(path) = env::var("NANOBOT_HANGOUTS_SERVICE_ACCOUNT_FILE") {
+            if let Some(ref mut hangouts) = self.hangouts {
+                hangouts.service_account_file = Some(path);
+            }
+        }
+
+        if let Ok(path) = env::var("NANOBOT_TEAMS_KEYS_FILE") {
+            if let Some(ref mut teams) = self.teams {
+                teams.openid_keys_file = Some(path);
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_url("node.url", &self.node.url)?;
+        validate_url("price.coinmarketcap_url", &self.price.coinmarketcap_url)?;
+        validate_url("price.coingecko_url", &self.price.coingecko_url)?;
+
+        for provider in &self.price.providers {
+            match provider.as_str() {
+                "coingecko" | "coinmarketcap" => (),
+                "file" if self.price.file.is_some() => (),
+                "file" => {
+                    return Err(ConfigError::Invalid(
+                        "the file price provider needs price.file".to_string(),
+                    ))
+                }
+                _ => {
+                    return Err(ConfigError::Invalid(format!(
+                        "unknown price provider {}",
+                        provider
+                    )))
+                }
+            }
+        }
+
+        if !SUPPORTED_CURRENCIES.contains(&self.price.default_currency.as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "price.default_currency {} is not supported",
+                self.price.default_currency
+            )));
+        }
+
+        if self.node.timeout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "node.timeout_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.database.path.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "database.path must not be empty".to_string(),
+            ));
+        }
+
+        if self.receiver.interval_secs == 0 || self.receiver.batch == 0 {
+            return Err(ConfigError::Invalid(
+                "receiver.interval_secs and receiver.batch must be greater than 0".to_string(),
+            ));
+        }
+
+        match (self.node.signing.as_str(), &self.node.representative) {
+            ("wallet", _) | ("local", &Some(_)) => (),
+            ("local", &None) => {
+                return Err(ConfigError::Invalid(
+                    "local signing needs node.representative".to_string(),
+                ))
+            }
+            (signing, _) => {
+                return Err(ConfigError::Invalid(format!(
+                    "node.signing must be wallet or local, not {}",
+                    signing
+                )))
+            }
+        }
+
+        if let Some(ref representative) = self.node.representative {
+            if let Err(e) = NanoAddress::parse(representative) {
+                return Err(ConfigError::Invalid(format!(
+                    "node.representative is not a valid address: {}",
+                    e
+                )));
+            }
+        }
+
+        if let Some(ref url) = self.node.websocket_url {
+            validate_url("node.websocket_url", url)?;
+        }
+
+        if self.custody.enabled && !self.receiver.enabled {
+            return Err(ConfigError::Invalid(
+                "custody needs the receiver to sweep deposits, enable receiver.enabled".to_string(),
+            ));
+        }
+
+        if self.notifications.poll_interval_secs == 0 || self.notifications.history_count == 0 {
+            return Err(ConfigError::Invalid(
+                "notifications.poll_interval_secs and notifications.history_count must be greater \
+                 than 0"
+                    .to_string(),
+            ));
+        }
+
+        for &(name, limit) in &[
+            ("rate_limit.user", &self.rate_limit.user),
+            ("rate_limit.space", &self.rate_limit.space),
+        ] {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "{} burst and per_minute must be greater than 0",
+                    name
+                )));
+            }
+        }
+
+        if let Some(ref hangouts) = self.hangouts {
+            let has_token = |value: &Option<String>| match *value {
+                Some(ref v) => !v.trim().is_empty(),
+                None => false,
+            };
+
+            if !has_token(&hangouts.verification_token) && !has_token(&hangouts.project_number) {
+                return Err(ConfigError::Invalid(
+                    "hangouts needs a verification_token or a project_number".to_string(),
+                ));
+            }
+
+            validate_url("hangouts.openid_keys_url", &hangouts.openid_keys_url)?;
+            validate_url("hangouts.chat_api_url", &hangouts.chat_api_url)?;
+        }
+
+        if let Some(ref teams) = self.teams {
+            if teams.app_id.trim().is_empty() || teams.app_password.trim().is_empty() {
+                return Err(ConfigError::Invalid(
+                    "teams.app_id and teams.app_password must not be empty".to_string(),
+                ));
+            }
+
+            validate_url("teams.token_url", &teams.token_url)?;
+            validate_url("teams.openid_keys_url", &teams.openid_keys_url)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_url(name: &str, url: &str) -> Result<(), ConfigError> {
+    match url.parse::<Uri>() {
+        Ok(ref uri) if uri.scheme().is_some() && uri.authority().is_some() => Ok(()),
+        _ => Err(ConfigError::Invalid(format!(
+            "{} is not a valid url: {}",
+            name, url
+        ))),
+    }
+}
+
+fn default_node_url() -> String {
+    "http://127.0.0.1:7076".to_string()
+}
+
+fn default_node_timeout_secs() -> u64 {
+    10
+}
+
+fn default_node_signing() -> String {
+    "wallet".to_string()
+}
+
+fn default_database_path() -> String {
+    "sqlite/main.database".to_string()
+}
+
+fn default_hangouts_openid_keys_url() -> String {
+    "https://www.googleapis.com/service_accounts/v1/jwk/chat@system.gserviceaccount.com".to_string()
+}
+
+fn default_hangouts_chat_api_url() -> String {
+    "https://chat.googleapis.com/v1".to_string()
+}
+
+fn default_teams_token_url() -> String {
+    "https://login.microsoftonline.com/botframework.com/oauth2/v2.0/token".to_string()
+}
+
+fn default_teams_openid_keys_url() -> String {
+    "https://login.botframework.com/v1/.well-known/keys".to_string()
+}
+
+fn default_price_providers() -> Vec<String> {
+    vec!["coingecko".to_string(), "coinmarketcap".to_string()]
+}
+
+fn default_coingecko_url() -> String {
+    "https://api.coingecko.com/api/v3/simple/price".to_string()
+}
+
+fn default_price_url() -> String {
+    "https://pro-api.coinmarketcap.com/v1/cryptocurrency/quotes/latest".to_string()
+}
+
+fn default_price_ttl_secs() -> u64 {
+    60
+}
+
+fn default_currency() -> String {
+    "EUR".to_string()
+}
+
+fn default_receiver_enabled() -> bool {
+    true
+}
+
+fn default_receiver_interval_secs() -> u64 {
+    30
+}
+
+fn default_receiver_batch() -> u32 {
+    50
+}
+
+fn default_notifications_enabled() -> bool {
+    true
+}
+
+fn default_notification_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_notification_history_count() -> u32 {
+    20
+}
+
+fn default_user_limit() -> BucketConfig {
+    BucketConfig {
+        burst: 5,
+        per_minute: 10,
+    }
+}
+
+fn default_space_limit() -> BucketConfig {
+    BucketConfig {
+        burst: 20,
+        per_minute: 60,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::config::Environment;
+    use std::sync::Mutex;
+
+    const REPRESENTATIVE: &str =
+        "nano_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3";
+
+    lazy_static! {
+        /// The environment is shared by every test thread.
+        static ref ENV: Mutex<()> = Mutex::new(());
+    }
+
+    fn clear_env() {
+        for (name, _) in env::vars().filter(|&(ref name, _)| name.starts_with("NANOBOT_")) {
+            env::remove_var(name);
+        }
+    }
+
+    fn rocket_config(nanobot: &str) -> rocket::Config {
+        let mut builder = rocket::Config::build(Environment::Development);
+
+        if !nanobot.is_empty() {
+            builder = builder.extra("nanobot", toml::from_str::<toml::Value>(nanobot).unwrap());
+        }
+
+        builder.finalize().expect("rocket config")
+    }
+
+    fn config_file(contents: &str) -> String {
+        let path: String = env::temp_dir()
+            .join(format!("nanobot-config-{}.toml", ::std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        fs::write(&path, contents).expect("config file");
+
+        path
+    }
+
+    #[test]
+    fn reads_the_rocket_nanobot_table() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        clear_env();
+
+        let config: Config = Config::load(&rocket_config(&format!(
+            r#"
+            [node]
+            url = "http://node.example.com:7076"
+            signing = " Local "
+            representative = "{}"
+
+            [price]
+            default_currency = "eur"
+            "#,
+            REPRESENTATIVE
+        ))).expect("config");
+
+        assert_eq!(config.node.url, "http://node.example.com:7076");
+        assert!(config.node.local_signing());
+        assert_eq!(config.price.default_currency, "EUR");
+        assert_eq!(config.database.path, default_database_path());
+
+        let defaults: Config = Config::load(&rocket_config("")).expect("default config");
+        assert_eq!(defaults.node.url, default_node_url());
+        assert!(!defaults.node.local_signing());
+    }
+
+    #[test]
+    fn prefers_the_environment_over_files() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        clear_env();
+
+        let path: String = config_file(
+            r#"
+            [node]
+            url = "http://file.example.com:7076"
+            timeout_secs = 5
+
+            [database]
+            path = "file.database"
+            "#,
+        );
+
+        env::set_var("NANOBOT_CONFIG", &path);
+        env::set_var("NANOBOT_NODE_URL", "http://env.example.com:7076");
+        env::set_var("NANOBOT_NODE_SIGNING", "WALLET");
+        env::set_var("NANOBOT_PRICE_PROVIDERS", "CoinGecko, ,coinmarketcap");
+
+        let loaded = Config::load(&rocket_config(
+            r#"
+            [database]
+            path = "rocket.database"
+            "#,
+        ));
+        clear_env();
+        let config: Config = loaded.expect("config");
+
+        assert_eq!(config.node.url, "http://env.example.com:7076");
+        assert_eq!(config.node.timeout_secs, 5);
+        assert_eq!(config.node.signing, "wallet");
+        assert_eq!(config.database.path, "file.database");
+        assert_eq!(config.price.providers, vec!["coingecko", "coinmarketcap"]);
+    }
+
+    #[test]
+    fn validates_every_section() {
+        let cases: &[(&str, fn(&mut Config))] = &[
+            ("node.url is not a valid url", |c| c.node.url = "node".to_string()),
+            ("price.coinmarketcap_url is not a valid url", |c| {
+                c.price.coinmarketcap_url = "".to_string()
+            }),
+            ("price.coingecko_url is not a valid url", |c| {
+                c.price.coingecko_url = "/simple/price".to_string()
+            }),
+            ("the file price provider needs price.file", |c| {
+                c.price.providers = vec!["file".to_string()]
+            }),
+            ("unknown price provider bitstamp", |c| {
+                c.price.providers = vec!["bitstamp".to_string()]
+            }),
+            ("price.default_currency XYZ is not supported", |c| {
+                c.price.default_currency = "XYZ".to_string()
+            }),
+            ("node.timeout_secs must be greater than 0", |c| c.node.timeout_secs = 0),
+            ("database.path must not be empty", |c| c.database.path = " ".to_string()),
+            ("receiver.interval_secs and receiver.batch", |c| c.receiver.batch = 0),
+            ("local signing needs node.representative", |c| {
+                c.node.signing = "local".to_string()
+            }),
+            ("node.signing must be wallet or local, not remote", |c| {
+                c.node.signing = "remote".to_string()
+            }),
+            ("node.representative is not a valid address", |c| {
+                c.node.representative = Some("nano_1".to_string())
+            }),
+            ("node.websocket_url is not a valid url", |c| {
+                c.node.websocket_url = Some("7078".to_string())
+            }),
+            ("custody needs the receiver", |c| {
+                c.custody.enabled = true;
+                c.receiver.enabled = false;
+            }),
+            ("notifications.poll_interval_secs", |c| c.notifications.history_count = 0),
+            ("rate_limit.user burst and per_minute", |c| c.rate_limit.user.burst = 0),
+            ("rate_limit.space burst and per_minute", |c| c.rate_limit.space.per_minute = 0),
+            ("hangouts needs a verification_token or a project_number", |c| {
+                c.hangouts = Some(toml::from_str(r#"verification_token = " ""#).unwrap())
+            }),
+            ("hangouts.chat_api_url is not a valid url", |c| {
+                let hangouts = "project_number = '1'\nchat_api_url = 'chat'";
+                c.hangouts = Some(toml::from_str(hangouts).unwrap())
+            }),
+            ("teams.app_id and teams.app_password", |c| {
+                c.teams = Some(toml::from_str("app_id = 'id'\napp_password = ''").unwrap())
+            }),
+        ];
+
+        assert!(Config::default().validate().is_ok());
+
+        for &(expected, change) in cases {
+            let mut config = Config::default();
+            change(&mut config);
+
+            match config.validate() {
+                Err(ConfigError::Invalid(ref e)) if e.starts_with(expected) => (),
+                Err(e) => panic!("expected `{}`, got `{}`", expected, e),
+                Ok(()) => panic!("expected `{}`, got a valid config", expected),
+            }
+        }
+    }
+}
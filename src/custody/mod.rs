@@ -0,0 +1,378 @@
+mod reconcile;
+
+pub use self::reconcile::reconcile;
+
+use db::ledger::{self, LedgerError, Posting, Sweep};
+use db::{self, KeyCipher};
+use futures::Future;
+use node::{Account, Amount, Key, NanoAddress, NanoRpcClient, RpcError, Signer};
+use rusqlite::Connection;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+
+/// The hot wallet is stored as an account of its own, under an identity no chat platform uses.
+const PLATFORM: &str = "custody";
+const HOT_WALLET: &str = "hot-wallet";
+
+#[derive(Debug)]
+pub enum CustodyError {
+    HotWallet(String),
+    Ledger(LedgerError),
+    Node(RpcError),
+    /// The node failed without saying whether the block was published, so the withdrawal stays
+    /// held in `ledger::WITHDRAWING` until an operator settles or refunds it.
+    Unsettled(RpcError),
+    /// A sweep reached the hot wallet but was neither recorded nor credited, so nothing retries
+    /// it. It shows as surplus in the reconciliation report until an operator books it.
+    Unrecorded { block: String, amount: Amount, cause: LedgerError },
+}
+
+impl fmt::Display for CustodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CustodyError::HotWallet(ref e) => write!(f, "Hot wallet is not available: {}", e),
+            CustodyError::Ledger(ref e) => write!(f, "{}", e),
+            CustodyError::Node(ref e) => write!(f, "{}", e),
+            CustodyError::Unsettled(ref e) => write!(f, "Withdrawal outcome unknown: {}", e),
+            CustodyError::Unrecorded {
+                ref block,
+                amount,
+                ref cause,
+            } => write!(
+                f,
+                "Sweep {} of {} was neither recorded nor credited: {}",
+                block, amount, cause
+            ),
+        }
+    }
+}
+
+impl Error for CustodyError {
+    fn description(&self) -> &str {
+        "custody error"
+    }
+}
+
+impl From<LedgerError> for CustodyError {
+    fn from(e: LedgerError) -> CustodyError {
+        CustodyError::Ledger(e)
+    }
+}
+
+impl CustodyError {
+    /// Short explanation that is safe to show in chat.
+    pub fn user_message(&self) -> &str {
+        match *self {
+            CustodyError::HotWallet(_) => "the hot wallet is not available",
+            CustodyError::Ledger(LedgerError::InsufficientFunds { .. }) => "insufficient balance",
+            CustodyError::Ledger(_) => "the ledger could not be updated",
+            CustodyError::Node(ref e) => e.user_message(),
+            CustodyError::Unsettled(_) => {
+                "the node did not confirm it, the amount stays on hold until it is checked"
+            }
+            CustodyError::Unrecorded { .. } => "the deposit could not be booked",
+        }
+    }
+}
+
+/// The account deposits are swept into and withdrawals are sent from.
+pub fn hot_wallet(db_conn: &Mutex<Connection>) -> Result<Account, CustodyError> {
+    match db::get_account(db_conn, PLATFORM, HOT_WALLET) {
+        Ok(a) => Ok(a),
+        Err(e) => Err(CustodyError::HotWallet(e.to_string())),
+    }
+}
+
+/// Loads the hot wallet, creating it the first time custody is enabled.
+pub fn open_hot_wallet(
+    db_conn: &Mutex<Connection>,
+    signer: &Signer,
+    cipher: &KeyCipher,
+) -> Result<Account, CustodyError> {
+    if let Ok(a) = hot_wallet(db_conn) {
+        return Ok(a);
+    }
+
+    let (key, wallet): (Key, String) = signer.create_key().map_err(CustodyError::Node)?;
+
+    let sealed_key = Key {
+        private: match cipher.encrypt(&key.account, &key.private) {
+            Ok(p) => p,
+            Err(e) => return Err(CustodyError::HotWallet(e.to_string())),
+        },
+        ..key
+    };
+
+    if let Err(e) = db::add_account(db_conn, &sealed_key, &wallet, PLATFORM, HOT_WALLET) {
+        return Err(CustodyError::HotWallet(e.to_string()));
+    }
+
+    hot_wallet(db_conn)
+}
+
+/// Sends everything `account` holds on chain to the hot wallet and credits it to its owner,
+/// returning the amount swept. The block is recorded before the credit, a credit that fails is
+/// retried by `credit_sweeps`. A sweep that is neither recorded nor credited is an
+/// `CustodyError::Unrecorded` error.
+pub fn sweep(
+    db_conn: &Mutex<Connection>,
+    node: &NanoRpcClient,
+    signer: &Signer,
+    account: &Account,
+    hot: &Account,
+) -> Result<Amount, CustodyError> {
+    let balance: Amount = match node.get_balance(&account.account).wait() {
+        Ok(b) => b.balance,
+        Err(e) => return Err(CustodyError::Node(e)),
+    };
+
+    if balance.is_zero() {
+        return Ok(balance);
+    }
+
+    let destination: NanoAddress = match NanoAddress::parse(&hot.account) {
+        Ok(a) => a,
+        Err(e) => return Err(CustodyError::HotWallet(e.to_string())),
+    };
+
+    let block: String = signer
+        .send(account, &destination, balance)
+        .map_err(CustodyError::Node)?;
+
+    let swept = Sweep {
+        block,
+        account_id: account.id,
+        amount: balance,
+    };
+
+    if let Err(e) = book_sweep(db_conn, &swept) {
+        println!("Swept {} from {}: {}", balance, account.account, e);
+
+        return Err(e);
+    }
+
+    Ok(balance)
+}
+
+/// Records a sent sweep and credits it. The sweep is only done once it is credited, and only
+/// retried later when it was at least recorded.
+fn book_sweep(db_conn: &Mutex<Connection>, sweep: &Sweep) -> Result<(), CustodyError> {
+    let recorded = ledger::add_sweep(db_conn, sweep);
+
+    match (recorded, credit_sweep(db_conn, sweep)) {
+        (Ok(()), credited) => credited,
+        (Err(e), Ok(())) => {
+            println!("Credited sweep {} but could not record it: {}", sweep.block, e);
+            Ok(())
+        }
+        (Err(e), Err(_)) => Err(CustodyError::Unrecorded {
+            block: sweep.block.to_owned(),
+            amount: sweep.amount,
+            cause: e,
+        }),
+    }
+}
+
+/// Credits every recorded sweep that is still waiting for it, returning the ones that failed
+/// again.
+pub fn credit_sweeps(db_conn: &Mutex<Connection>) -> Result<Vec<Sweep>, CustodyError> {
+    let mut failed: Vec<Sweep> = Vec::new();
+
+    for sweep in ledger::get_pending_sweeps(db_conn)? {
+        if let Err(e) = credit_sweep(db_conn, &sweep) {
+            println!("Could not credit sweep {}: {}", sweep.block, e);
+            failed.push(sweep);
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Books the deposit of a sweep and marks it credited. Posting is idempotent on the block, so a
+/// sweep that stays unmarked is booked once and only marked on a later pass.
+fn credit_sweep(db_conn: &Mutex<Connection>, sweep: &Sweep) -> Result<(), CustodyError> {
+    ledger::post(
+        db_conn,
+        &Posting {
+            debit: ledger::EXTERNAL,
+            credit: &ledger::user_account(sweep.account_id),
+            amount: sweep.amount,
+            kind: ledger::DEPOSIT,
+            reference: &sweep.block,
+        },
+    )?;
+
+    if let Err(e) = ledger::mark_sweep_credited(db_conn, &sweep.block) {
+        println!("Credited sweep {} but could not mark it: {}", sweep.block, e);
+    }
+
+    Ok(())
+}
+
+/// Pays `amount` of the owner's ledger balance out of the hot wallet, returning the block hash.
+/// The amount is held in `ledger::WITHDRAWING` while the node works, settled once the block is
+/// published and refunded only when the node refused to send it.
+pub fn withdraw(
+    db_conn: &Mutex<Connection>,
+    signer: &Signer,
+    account: &Account,
+    destination: &NanoAddress,
+    amount: Amount,
+) -> Result<String, CustodyError> {
+    let hot: Account = hot_wallet(db_conn)?;
+    let owner: String = ledger::user_account(account.id);
+
+    let hold: i64 = ledger::post(
+        db_conn,
+        &Posting {
+            debit: &owner,
+            credit: ledger::WITHDRAWING,
+            amount,
+            kind: ledger::WITHDRAWAL,
+            reference: &destination.to_string(),
+        },
+    )?;
+
+    let error: RpcError = match signer.send(&hot, destination, amount) {
+        Ok(block) => {
+            let settled = ledger::post(
+                db_conn,
+                &Posting {
+                    debit: ledger::WITHDRAWING,
+                    credit: ledger::EXTERNAL,
+                    amount,
+                    kind: ledger::SETTLEMENT,
+                    reference: &block,
+                },
+            );
+
+            if let Err(e) = settled {
+                println!("Withdrawal {} was sent in {} but not settled: {}", hold, block, e);
+            }
+
+            return Ok(block);
+        }
+        Err(e) => e,
+    };
+
+    if !was_refused(&error) {
+        println!("Withdrawal {} of {} is held, the node failed: {}", hold, owner, error);
+
+        return Err(CustodyError::Unsettled(error));
+    }
+
+    let refunded = ledger::post(
+        db_conn,
+        &Posting {
+            debit: ledger::WITHDRAWING,
+            credit: &owner,
+            amount,
+            kind: ledger::REFUND,
+            reference: &hold.to_string(),
+        },
+    );
+
+    if let Err(e) = refunded {
+        println!("Withdrawal {} failed but could not be refunded: {}", hold, e);
+    }
+
+    Err(CustodyError::Node(error))
+}
+
+/// Whether the send certainly did not happen. Timeouts and dropped connections may have
+/// published the block anyway.
+fn was_refused(error: &RpcError) -> bool {
+    match *error {
+        RpcError::Node(_) | RpcError::Signing(_) | RpcError::InvalidUrl(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(block: &str, account_id: i64, nano: &str) -> Sweep {
+        Sweep {
+            block: block.to_string(),
+            account_id,
+            amount: Amount::from_nano_str(nano).unwrap(),
+        }
+    }
+
+    #[test]
+    fn credits_recorded_sweeps_once() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+
+        ledger::add_sweep(&db_conn, &sweep("A", 1, "2")).unwrap();
+        ledger::add_sweep(&db_conn, &sweep("B", 2, "3")).unwrap();
+        ledger::add_sweep(&db_conn, &sweep("A", 1, "2")).unwrap();
+
+        // Booked before the process stopped, but never marked.
+        credit_sweep(&db_conn, &sweep("B", 2, "3")).unwrap();
+        db_conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE sweeps SET credited_at = NULL", &[])
+            .unwrap();
+
+        assert!(credit_sweeps(&db_conn).unwrap().is_empty());
+        assert!(credit_sweeps(&db_conn).unwrap().is_empty());
+        assert!(ledger::get_pending_sweeps(&db_conn).unwrap().is_empty());
+
+        let alice: Amount = ledger::balance(&db_conn, &ledger::user_account(1)).unwrap();
+        let bob: Amount = ledger::balance(&db_conn, &ledger::user_account(2)).unwrap();
+
+        assert_eq!(alice, Amount::from_nano_str("2").unwrap());
+        assert_eq!(bob, Amount::from_nano_str("3").unwrap());
+        assert!(ledger::check(&db_conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_sweeps_that_are_neither_recorded_nor_credited() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+        let drop = |table: &str| {
+            db_conn
+                .lock()
+                .unwrap()
+                .execute(&format!("DROP TABLE {}", table), &[])
+                .unwrap();
+        };
+
+        // Credited without a record is done, nothing is left to retry.
+        drop("sweeps");
+        book_sweep(&db_conn, &sweep("A", 1, "1")).expect("credited sweep");
+
+        let credited: Amount = ledger::balance(&db_conn, &ledger::user_account(1)).unwrap();
+        assert_eq!(credited, Amount::from_nano_str("1").unwrap());
+
+        drop("ledger_balances");
+
+        match book_sweep(&db_conn, &sweep("B", 1, "2")) {
+            Err(CustodyError::Unrecorded {
+                ref block, amount, ..
+            }) => {
+                assert_eq!(block, "B");
+                assert_eq!(amount, Amount::from_nano_str("2").unwrap());
+            }
+            _ => panic!("a lost sweep was not reported"),
+        }
+    }
+
+    #[test]
+    fn keeps_sweeps_whose_credit_fails() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+
+        // The block is already booked for someone else, so the credit is refused.
+        credit_sweep(&db_conn, &sweep("A", 2, "1")).unwrap();
+        ledger::add_sweep(&db_conn, &sweep("A", 1, "1")).unwrap();
+
+        let failed: Vec<Sweep> = credit_sweeps(&db_conn).unwrap();
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].block, "A");
+        assert_eq!(ledger::get_pending_sweeps(&db_conn).unwrap().len(), 1);
+        assert!(ledger::balance(&db_conn, &ledger::user_account(1)).unwrap().is_zero());
+    }
+}
@@ -0,0 +1,154 @@
+use custody::{self, CustodyError};
+use db;
+use db::ledger::{self, LedgerError, Sweep};
+use futures::Future;
+use node::{Account, Amount, Balance, NanoRpcClient};
+use rusqlite::Connection;
+use std::fmt;
+use std::sync::Mutex;
+
+/// What the ledger owes users next to what custody holds on chain.
+pub struct Report {
+    /// Sum of every user balance in the ledger.
+    pub owed: Amount,
+    /// Withdrawals taken from users that are not settled yet.
+    pub withdrawing: Amount,
+    /// Balance and pending blocks of the hot wallet. Sweeps are credited when they are sent, so
+    /// pending sweeps count as held.
+    pub held: Amount,
+    /// Funds still in user deposit accounts, not credited until they are swept.
+    pub unswept: Amount,
+    /// Sweeps sent to the hot wallet whose credit failed again during this check.
+    pub uncredited: Amount,
+    pub violations: Vec<String>,
+}
+
+impl Report {
+    /// How much the hot wallet is short of what users are owed.
+    pub fn deficit(&self) -> Option<Amount> {
+        match self.owed.checked_sub(self.held) {
+            Some(d) if !d.is_zero() => Some(d),
+            _ => None,
+        }
+    }
+
+    /// How much the hot wallet holds beyond balances and unsettled withdrawals, such as direct
+    /// payments to it or sweeps that could not be credited.
+    pub fn surplus(&self) -> Option<Amount> {
+        let committed: Amount = self.owed.checked_add(self.withdrawing)?;
+
+        match self.held.checked_sub(committed) {
+            Some(s) if !s.is_zero() => Some(s),
+            _ => None,
+        }
+    }
+
+    /// No broken invariants and enough funds to pay every user out.
+    pub fn is_healthy(&self) -> bool {
+        self.violations.is_empty() && self.deficit().is_none()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: &[(&str, Amount)] = &[
+            ("Owed to users", self.owed),
+            ("Unsettled withdrawals", self.withdrawing),
+            ("Held in the hot wallet", self.held),
+            ("Awaiting sweep", self.unswept),
+            ("Swept, not credited", self.uncredited),
+        ];
+
+        for &(name, amount) in lines {
+            writeln!(f, "{:<24}{} ({} raw)", name, amount, amount.raw())?;
+        }
+
+        match (self.deficit(), self.surplus()) {
+            (Some(d), _) => writeln!(f, "Deficit of {} ({} raw)", d, d.raw())?,
+            (_, Some(s)) => writeln!(f, "Surplus of {} ({} raw)", s, s.raw())?,
+            _ if !self.withdrawing.is_zero() => writeln!(
+                f,
+                "Balanced once the unsettled withdrawals are checked on chain"
+            )?,
+            _ => writeln!(f, "Balanced")?,
+        }
+
+        for violation in &self.violations {
+            writeln!(f, "Invariant broken: {}", violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Credits sweeps still waiting for it, then checks the ledger invariants and compares its
+/// balances with the chain.
+pub fn reconcile(
+    db_conn: &Mutex<Connection>,
+    node: &NanoRpcClient,
+) -> Result<Report, CustodyError> {
+    let uncredited: Vec<Sweep> = custody::credit_sweeps(db_conn)?;
+
+    let mut report = Report {
+        owed: Amount::default(),
+        withdrawing: Amount::default(),
+        held: Amount::default(),
+        unswept: Amount::default(),
+        uncredited: Amount::default(),
+        violations: ledger::check(db_conn)?,
+    };
+
+    for sweep in &uncredited {
+        report.uncredited = add(report.uncredited, sweep.amount)?;
+        report.violations.push(format!(
+            "sweep {} of {} is not credited to {}",
+            sweep.block,
+            sweep.amount,
+            ledger::user_account(sweep.account_id)
+        ));
+    }
+
+    for balance in ledger::get_balances(db_conn)? {
+        let available: Amount = balance.available().unwrap_or_default();
+
+        match balance.account.as_str() {
+            ledger::EXTERNAL => (),
+            ledger::WITHDRAWING => report.withdrawing = add(report.withdrawing, available)?,
+            account if ledger::is_user_account(account) => {
+                report.owed = add(report.owed, available)?
+            }
+            account => report
+                .violations
+                .push(format!("{} is not a known ledger account", account)),
+        }
+    }
+
+    let hot: Account = custody::hot_wallet(db_conn)?;
+    let accounts: Vec<Account> = match db::get_all_accounts(db_conn) {
+        Ok(a) => a,
+        Err(e) => return Err(CustodyError::Ledger(LedgerError::Database(e))),
+    };
+
+    for account in &accounts {
+        let chain: Balance = match node.get_balance(&account.account).wait() {
+            Ok(b) => b,
+            Err(e) => return Err(CustodyError::Node(e)),
+        };
+        let total: Amount = add(chain.balance, chain.pending)?;
+
+        if account.id == hot.id {
+            report.held = total;
+        } else {
+            report.unswept = add(report.unswept, total)?;
+        }
+    }
+
+    Ok(report)
+}
+
+fn add(a: Amount, b: Amount) -> Result<Amount, CustodyError> {
+    match a.checked_add(b) {
+        Some(sum) => Ok(sum),
+        None => Err(CustodyError::Ledger(LedgerError::Overflow)),
+    }
+}
@@ -0,0 +1,266 @@
+use hex;
+use node::Account;
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+const PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 12;
+
+/// Seals the `private` column with ChaCha20-Poly1305, using the account address as associated
+/// data so a ciphertext cannot be moved to another row.
+pub struct KeyCipher {
+    sealing_key: SealingKey,
+    opening_key: OpeningKey,
+    rng: SystemRandom,
+}
+
+#[derive(Debug)]
+pub enum CipherError {
+    MissingKey,
+    InvalidKey(String),
+    KeyFile(io::Error),
+    Encrypt,
+    Decrypt,
+}
+
+impl fmt::Display for CipherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CipherError::MissingKey => write!(
+                f,
+                "No master key configured, set NANOBOT_MASTER_KEY or NANOBOT_MASTER_KEY_FILE"
+            ),
+            CipherError::InvalidKey(ref e) => write!(f, "Invalid master key: {}", e),
+            CipherError::KeyFile(ref e) => write!(f, "Could not read the master key file: {}", e),
+            CipherError::Encrypt => write!(f, "Could not encrypt the private key"),
+            CipherError::Decrypt => write!(f, "Could not decrypt the private key"),
+        }
+    }
+}
+
+impl Error for CipherError {
+    fn description(&self) -> &str {
+        "private key encryption error"
+    }
+}
+
+impl KeyCipher {
+    /// Reads a hex encoded 32 byte master key from `NANOBOT_MASTER_KEY`, or from the file named
+    /// by `NANOBOT_MASTER_KEY_FILE`.
+    pub fn from_env() -> Result<KeyCipher, CipherError> {
+        let encoded: String = match env::var("NANOBOT_MASTER_KEY") {
+            Ok(k) => k,
+            Err(_) => match env::var("NANOBOT_MASTER_KEY_FILE") {
+                Ok(path) => match fs::read_to_string(path) {
+                    Ok(k) => k,
+                    Err(e) => return Err(CipherError::KeyFile(e)),
+                },
+                Err(_) => return Err(CipherError::MissingKey),
+            },
+        };
+
+        KeyCipher::from_hex(encoded.trim())
+    }
+
+    pub fn from_hex(encoded: &str) -> Result<KeyCipher, CipherError> {
+        let key: Vec<u8> = match hex::decode(encoded) {
+            Ok(k) => k,
+            Err(e) => return Err(CipherError::InvalidKey(e.to_string())),
+        };
+
+        if key.len() != CHACHA20_POLY1305.key_len() {
+            return Err(CipherError::InvalidKey(format!(
+                "expected {} bytes, got {}",
+                CHACHA20_POLY1305.key_len(),
+                key.len()
+            )));
+        }
+
+        match (
+            SealingKey::new(&CHACHA20_POLY1305, &key),
+            OpeningKey::new(&CHACHA20_POLY1305, &key),
+        ) {
+            (Ok(sealing_key), Ok(opening_key)) => Ok(KeyCipher {
+                sealing_key,
+                opening_key,
+                rng: SystemRandom::new(),
+            }),
+            _ => Err(CipherError::InvalidKey("rejected by cipher".to_string())),
+        }
+    }
+
+    pub fn is_encrypted(private: &str) -> bool {
+        private.starts_with(PREFIX)
+    }
+
+    pub fn encrypt(&self, account: &str, private: &str) -> Result<String, CipherError> {
+        let mut nonce = [0u8; NONCE_LEN];
+
+        if self.rng.fill(&mut nonce).is_err() {
+            return Err(CipherError::Encrypt);
+        }
+
+        let tag_len: usize = CHACHA20_POLY1305.tag_len();
+        let mut in_out: Vec<u8> = private.as_bytes().to_vec();
+        in_out.extend(vec![0u8; tag_len]);
+
+        let sealed_len: usize = match aead::seal_in_place(
+            &self.sealing_key,
+            &nonce,
+            account.as_bytes(),
+            &mut in_out,
+            tag_len,
+        ) {
+            Ok(l) => l,
+            Err(_) => return Err(CipherError::Encrypt),
+        };
+
+        let mut sealed: Vec<u8> = nonce.to_vec();
+        sealed.extend_from_slice(&in_out[..sealed_len]);
+
+        Ok(format!("{}{}", PREFIX, hex::encode(sealed)))
+    }
+
+    pub fn decrypt(&self, account: &str, stored: &str) -> Result<String, CipherError> {
+        if !KeyCipher::is_encrypted(stored) {
+            return Err(CipherError::Decrypt);
+        }
+
+        let mut sealed: Vec<u8> = match hex::decode(&stored[PREFIX.len()..]) {
+            Ok(s) => s,
+            Err(_) => return Err(CipherError::Decrypt),
+        };
+
+        if sealed.len() < NONCE_LEN {
+            return Err(CipherError::Decrypt);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at_mut(NONCE_LEN);
+
+        match aead::open_in_place(
+            &self.opening_key,
+            nonce,
+            account.as_bytes(),
+            0,
+            ciphertext,
+        ) {
+            Ok(plaintext) => match String::from_utf8(plaintext.to_vec()) {
+                Ok(p) => Ok(p),
+                Err(_) => Err(CipherError::Decrypt),
+            },
+            Err(_) => Err(CipherError::Decrypt),
+        }
+    }
+
+    /// Only call this right before signing; everywhere else the key stays sealed.
+    pub fn private_key(&self, account: &Account) -> Result<String, CipherError> {
+        self.decrypt(&account.account, &account.private)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+    use node::Key;
+
+    const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const ACCOUNT: &str = "nano_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3";
+    const OTHER_ACCOUNT: &str = "nano_1111111111111111111111111111111111111111111111111111hifc8npp";
+    const PRIVATE: &str = "34F0A37AAD20F4A260F0A5B3CB3D7FB50673212263E58A380BC10474BB039CE4";
+
+    fn cipher() -> KeyCipher {
+        KeyCipher::from_hex(MASTER_KEY).expect("valid master key")
+    }
+
+    #[test]
+    fn round_trips_private_keys() {
+        let cipher = cipher();
+        let sealed: String = cipher.encrypt(ACCOUNT, PRIVATE).unwrap();
+
+        assert!(KeyCipher::is_encrypted(&sealed));
+        assert!(!sealed.contains(PRIVATE));
+        assert_ne!(sealed, cipher.encrypt(ACCOUNT, PRIVATE).unwrap());
+        assert_eq!(cipher.decrypt(ACCOUNT, &sealed).unwrap(), PRIVATE);
+    }
+
+    #[test]
+    fn rejects_keys_moved_to_another_account() {
+        let cipher = cipher();
+        let sealed: String = cipher.encrypt(ACCOUNT, PRIVATE).unwrap();
+
+        assert!(cipher.decrypt(OTHER_ACCOUNT, &sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let cipher = cipher();
+        let sealed: String = cipher.encrypt(ACCOUNT, PRIVATE).unwrap();
+
+        // Flip one bit of the ciphertext, past the prefix and the nonce
+        let position: usize = PREFIX.len() + NONCE_LEN * 2 + 4;
+        let flipped: char = match &sealed[position..position + 1] {
+            "0" => '1',
+            _ => '0',
+        };
+        let mut tampered: String = sealed.to_owned();
+        tampered.replace_range(position..position + 1, &flipped.to_string());
+
+        assert!(cipher.decrypt(ACCOUNT, &tampered).is_err());
+        assert!(cipher.decrypt(ACCOUNT, &sealed[..sealed.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn rejects_values_without_prefix() {
+        let cipher = cipher();
+        let sealed: String = cipher.encrypt(ACCOUNT, PRIVATE).unwrap();
+
+        assert!(cipher.decrypt(ACCOUNT, &sealed[PREFIX.len()..]).is_err());
+        assert!(cipher.decrypt(ACCOUNT, PRIVATE).is_err());
+        assert!(!KeyCipher::is_encrypted(PRIVATE));
+    }
+
+    #[test]
+    fn rejects_invalid_master_keys() {
+        assert!(KeyCipher::from_hex("00").is_err());
+        assert!(KeyCipher::from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn encrypt_keys_seals_only_plaintext_keys() {
+        let cipher = cipher();
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+
+        let plaintext = Key {
+            account: ACCOUNT.to_string(),
+            public: String::new(),
+            private: PRIVATE.to_string(),
+        };
+        let sealed = Key {
+            account: OTHER_ACCOUNT.to_string(),
+            public: String::new(),
+            private: cipher.encrypt(OTHER_ACCOUNT, PRIVATE).unwrap(),
+        };
+
+        db::add_account(&db_conn, &plaintext, "", "teams", "plaintext").unwrap();
+        db::add_account(&db_conn, &sealed, "", "teams", "sealed").unwrap();
+
+        assert_eq!(db::count_plaintext_keys(&db_conn).unwrap(), 1);
+        assert_eq!(db::encrypt_private_keys(&db_conn, &cipher).unwrap(), 1);
+        assert_eq!(db::count_plaintext_keys(&db_conn).unwrap(), 0);
+        assert_eq!(db::encrypt_private_keys(&db_conn, &cipher).unwrap(), 0);
+
+        let resealed: Account = db::get_account(&db_conn, "teams", "sealed").unwrap();
+        assert_eq!(resealed.private, sealed.private);
+
+        for id in &["plaintext", "sealed"] {
+            let account: Account = db::get_account(&db_conn, "teams", id).unwrap();
+            assert_eq!(cipher.private_key(&account).unwrap(), PRIVATE);
+        }
+    }
+}
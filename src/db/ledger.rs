@@ -0,0 +1,514 @@
+use chrono::Utc;
+use node::Amount;
+use rusqlite::{Connection, Error};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Funds entering or leaving custody on chain. It is the only ledger account allowed to go
+/// negative, and is always the negated sum of every other account.
+pub const EXTERNAL: &str = "external";
+
+/// Withdrawals taken from a user but not yet settled, either waiting for the node or failed
+/// without a clear outcome.
+pub const WITHDRAWING: &str = "withdrawing";
+
+pub const DEPOSIT: &str = "deposit";
+pub const TIP: &str = "tip";
+pub const WITHDRAWAL: &str = "withdrawal";
+pub const SETTLEMENT: &str = "settlement";
+pub const REFUND: &str = "refund";
+
+const USER_PREFIX: &str = "user:";
+
+/// Kinds whose reference is a block hash that may only be booked once. Posting the same entry
+/// again returns the booked one, so credits can be retried safely.
+const UNIQUE_KINDS: &[&str] = &[DEPOSIT, SETTLEMENT];
+
+/// Moves `amount` from `debit` to `credit`. Each entry is both sides of the booking, so the
+/// ledger always sums to zero.
+pub struct Posting<'a> {
+    pub debit: &'a str,
+    pub credit: &'a str,
+    pub amount: Amount,
+    pub kind: &'a str,
+    pub reference: &'a str,
+}
+
+/// A block sent from a deposit account to the hot wallet. It is recorded before its owner is
+/// credited, so a credit that fails is retried instead of lost.
+pub struct Sweep {
+    pub block: String,
+    pub account_id: i64,
+    pub amount: Amount,
+}
+
+pub struct LedgerBalance {
+    pub account: String,
+    pub credits: Amount,
+    pub debits: Amount,
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    Database(Error),
+    InvalidPosting(&'static str),
+    InsufficientFunds { account: String, available: Amount },
+    Duplicate { kind: String, reference: String },
+    Overflow,
+    Corrupt(String),
+}
+
+impl LedgerBalance {
+    /// Credits left after debits, `None` when the account is overdrawn.
+    pub fn available(&self) -> Option<Amount> {
+        self.credits.checked_sub(self.debits)
+    }
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LedgerError::Database(ref e) => write!(f, "Ledger database error: {}", e),
+            LedgerError::InvalidPosting(reason) => write!(f, "Invalid ledger posting: {}", reason),
+            LedgerError::InsufficientFunds {
+                ref account,
+                available,
+            } => write!(f, "{} only has {} available", account, available),
+            LedgerError::Duplicate {
+                ref kind,
+                ref reference,
+            } => write!(f, "{} {} is already booked", kind, reference),
+            LedgerError::Overflow => write!(f, "Ledger amount overflows"),
+            LedgerError::Corrupt(ref e) => write!(f, "Ledger is inconsistent: {}", e),
+        }
+    }
+}
+
+impl error::Error for LedgerError {
+    fn description(&self) -> &str {
+        "ledger error"
+    }
+}
+
+impl From<Error> for LedgerError {
+    fn from(e: Error) -> LedgerError {
+        LedgerError::Database(e)
+    }
+}
+
+/// Ledger account of a bot account, shared by every identity linked to it.
+pub fn user_account(account_id: i64) -> String {
+    format!("{}{}", USER_PREFIX, account_id)
+}
+
+pub fn is_user_account(account: &str) -> bool {
+    account.starts_with(USER_PREFIX)
+}
+
+/// Books `posting` and updates both balances in one transaction, returning the entry id. Only
+/// `EXTERNAL` may be debited beyond its credits. A block that is already booked returns its entry
+/// when the posting is the same and `LedgerError::Duplicate` when it is not.
+pub fn post(db_conn: &Mutex<Connection>, posting: &Posting) -> Result<i64, LedgerError> {
+    if posting.amount.is_zero() {
+        return Err(LedgerError::InvalidPosting("the amount must be greater than zero"));
+    }
+
+    if posting.debit == posting.credit {
+        return Err(LedgerError::InvalidPosting("debit and credit must be different accounts"));
+    }
+
+    let mut conn = db_conn.lock().expect("db connection lock");
+    let tx = conn.transaction()?;
+
+    if UNIQUE_KINDS.contains(&posting.kind) {
+        let booked = tx.query_row(
+            "SELECT id, debit, credit, amount FROM ledger_entries
+             WHERE kind = ?1 AND reference = ?2",
+            &[&posting.kind, &posting.reference],
+            |row| -> (i64, String, String, String) {
+                (row.get(0), row.get(1), row.get(2), row.get(3))
+            },
+        );
+
+        match booked {
+            Ok((id, ref debit, ref credit, ref amount))
+                if debit == posting.debit
+                    && credit == posting.credit
+                    && parse_raw(amount)? == posting.amount =>
+            {
+                return Ok(id)
+            }
+            Ok(_) => {
+                return Err(LedgerError::Duplicate {
+                    kind: posting.kind.to_string(),
+                    reference: posting.reference.to_string(),
+                })
+            }
+            Err(Error::QueryReturnedNoRows) => (),
+            Err(e) => return Err(LedgerError::Database(e)),
+        }
+    }
+
+    let debit: LedgerBalance = read_balance(&tx, posting.debit)?;
+    let credit: LedgerBalance = read_balance(&tx, posting.credit)?;
+
+    let debits: Amount = match debit.debits.checked_add(posting.amount) {
+        Some(d) => d,
+        None => return Err(LedgerError::Overflow),
+    };
+    let credits: Amount = match credit.credits.checked_add(posting.amount) {
+        Some(c) => c,
+        None => return Err(LedgerError::Overflow),
+    };
+
+    if posting.debit != EXTERNAL && debits > debit.credits {
+        return Err(LedgerError::InsufficientFunds {
+            account: posting.debit.to_string(),
+            available: debit.available().unwrap_or_default(),
+        });
+    }
+
+    tx.execute(
+        "INSERT INTO ledger_entries (debit, credit, amount, kind, reference, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        &[
+            &posting.debit,
+            &posting.credit,
+            &posting.amount.raw().to_string(),
+            &posting.kind,
+            &posting.reference,
+            &Utc::now().to_rfc3339(),
+        ],
+    )?;
+
+    let id: i64 = tx.last_insert_rowid();
+
+    write_balance(&tx, posting.debit, debit.credits, debits)?;
+    write_balance(&tx, posting.credit, credits, credit.debits)?;
+
+    tx.commit()?;
+
+    Ok(id)
+}
+
+/// What `account` can spend; zero for accounts that were never booked.
+pub fn balance(db_conn: &Mutex<Connection>, account: &str) -> Result<Amount, LedgerError> {
+    let conn = db_conn.lock().expect("db connection lock");
+
+    match read_balance(&conn, account)?.available() {
+        Some(a) => Ok(a),
+        None => Err(LedgerError::Corrupt(format!("{} is overdrawn", account))),
+    }
+}
+
+/// Records a sweep as waiting for its credit. Recording the same block again changes nothing.
+pub fn add_sweep(db_conn: &Mutex<Connection>, sweep: &Sweep) -> Result<(), LedgerError> {
+    db_conn.lock().expect("db connection lock").execute(
+        "INSERT OR IGNORE INTO sweeps (block, account_id, amount, created_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        &[
+            &sweep.block,
+            &sweep.account_id,
+            &sweep.amount.raw().to_string(),
+            &Utc::now().to_rfc3339(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+pub fn mark_sweep_credited(db_conn: &Mutex<Connection>, block: &str) -> Result<(), LedgerError> {
+    db_conn.lock().expect("db connection lock").execute(
+        "UPDATE sweeps SET credited_at = ?1 WHERE block = ?2",
+        &[&Utc::now().to_rfc3339(), &block],
+    )?;
+
+    Ok(())
+}
+
+/// Sweeps that were sent but not credited yet, oldest first.
+pub fn get_pending_sweeps(db_conn: &Mutex<Connection>) -> Result<Vec<Sweep>, LedgerError> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let mut stmt = conn.prepare(
+        "SELECT block, account_id, amount FROM sweeps WHERE credited_at IS NULL
+         ORDER BY created_at",
+    )?;
+    let rows = stmt.query_map(&[], |row| -> (String, i64, String) {
+        (row.get(0), row.get(1), row.get(2))
+    })?;
+
+    let mut sweeps: Vec<Sweep> = Vec::new();
+
+    for row in rows {
+        let (block, account_id, amount) = row?;
+
+        sweeps.push(Sweep {
+            block,
+            account_id,
+            amount: parse_raw(&amount)?,
+        });
+    }
+
+    Ok(sweeps)
+}
+
+pub fn get_balances(db_conn: &Mutex<Connection>) -> Result<Vec<LedgerBalance>, LedgerError> {
+    read_balances(&db_conn.lock().expect("db connection lock"))
+}
+
+/// Replays every entry and compares the result with the stored balances, returning each broken
+/// invariant. An empty list means the ledger is consistent.
+pub fn check(db_conn: &Mutex<Connection>) -> Result<Vec<String>, LedgerError> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let mut violations: Vec<String> = Vec::new();
+    let mut replayed: HashMap<String, (Amount, Amount)> = HashMap::new();
+
+    {
+        let mut stmt = conn.prepare("SELECT id, debit, credit, amount FROM ledger_entries")?;
+        let rows = stmt.query_map(&[], |row| -> (i64, String, String, String) {
+            (row.get(0), row.get(1), row.get(2), row.get(3))
+        })?;
+
+        for row in rows {
+            let (id, debit, credit, amount) = row?;
+            let amount: Amount = parse_raw(&amount)?;
+
+            if amount.is_zero() || debit == credit {
+                violations.push(format!("entry {} moves nothing", id));
+            }
+
+            {
+                let debited = replayed.entry(debit).or_insert_with(Default::default);
+                debited.1 = debited.1.checked_add(amount).ok_or(LedgerError::Overflow)?;
+            }
+
+            let credited = replayed.entry(credit).or_insert_with(Default::default);
+            credited.0 = credited.0.checked_add(amount).ok_or(LedgerError::Overflow)?;
+        }
+    }
+
+    let mut total_credits: u128 = 0;
+    let mut total_debits: u128 = 0;
+
+    for balance in read_balances(&conn)? {
+        let expected: (Amount, Amount) = replayed.remove(&balance.account).unwrap_or_default();
+
+        if expected != (balance.credits, balance.debits) {
+            violations.push(format!(
+                "{} is stored as {} credited and {} debited, its entries add up to {} and {}",
+                balance.account, balance.credits, balance.debits, expected.0, expected.1
+            ));
+        }
+
+        if balance.account != EXTERNAL && balance.available().is_none() {
+            violations.push(format!("{} is overdrawn", balance.account));
+        }
+
+        total_credits = total_credits.wrapping_add(balance.credits.raw());
+        total_debits = total_debits.wrapping_add(balance.debits.raw());
+    }
+
+    for account in replayed.keys() {
+        violations.push(format!("{} has entries but no stored balance", account));
+    }
+
+    if total_credits != total_debits {
+        violations.push("stored credits and debits do not add up to zero".to_string());
+    }
+
+    Ok(violations)
+}
+
+fn read_balances(conn: &Connection) -> Result<Vec<LedgerBalance>, LedgerError> {
+    let mut stmt =
+        conn.prepare("SELECT account, credits, debits FROM ledger_balances ORDER BY account")?;
+    let rows = stmt.query_map(&[], |row| -> (String, String, String) {
+        (row.get(0), row.get(1), row.get(2))
+    })?;
+
+    let mut balances: Vec<LedgerBalance> = Vec::new();
+
+    for row in rows {
+        let (account, credits, debits) = row?;
+
+        balances.push(LedgerBalance {
+            account,
+            credits: parse_raw(&credits)?,
+            debits: parse_raw(&debits)?,
+        });
+    }
+
+    Ok(balances)
+}
+
+fn read_balance(conn: &Connection, account: &str) -> Result<LedgerBalance, LedgerError> {
+    let row = conn.query_row(
+        "SELECT credits, debits FROM ledger_balances WHERE account = ?",
+        &[&account],
+        |row| -> (String, String) { (row.get(0), row.get(1)) },
+    );
+
+    let (credits, debits): (Amount, Amount) = match row {
+        Ok((c, d)) => (parse_raw(&c)?, parse_raw(&d)?),
+        Err(Error::QueryReturnedNoRows) => (Amount::default(), Amount::default()),
+        Err(e) => return Err(LedgerError::Database(e)),
+    };
+
+    Ok(LedgerBalance {
+        account: account.to_string(),
+        credits,
+        debits,
+    })
+}
+
+fn write_balance(
+    conn: &Connection,
+    account: &str,
+    credits: Amount,
+    debits: Amount,
+) -> Result<(), LedgerError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ledger_balances (account, credits, debits) VALUES (?1, ?2, ?3)",
+        &[
+            &account,
+            &credits.raw().to_string(),
+            &debits.raw().to_string(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn parse_raw(raw: &str) -> Result<Amount, LedgerError> {
+    match Amount::from_raw_str(raw) {
+        Ok(a) => Ok(a),
+        Err(_) => Err(LedgerError::Corrupt(format!("`{}` is not an amount", raw))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db;
+
+    fn nano(n: &str) -> Amount {
+        Amount::from_nano_str(n).unwrap()
+    }
+
+    fn deposit(db_conn: &Mutex<Connection>, account: &str, amount: Amount, block: &str) {
+        post(
+            db_conn,
+            &Posting {
+                debit: EXTERNAL,
+                credit: account,
+                amount,
+                kind: DEPOSIT,
+                reference: block,
+            },
+        ).expect("deposit");
+    }
+
+    #[test]
+    fn transfers_between_users() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+        let (alice, bob) = (user_account(1), user_account(2));
+
+        deposit(&db_conn, &alice, nano("5"), "A");
+
+        post(
+            &db_conn,
+            &Posting {
+                debit: &alice,
+                credit: &bob,
+                amount: nano("2"),
+                kind: TIP,
+                reference: "bob",
+            },
+        ).expect("tip");
+
+        assert_eq!(balance(&db_conn, &alice).unwrap(), nano("3"));
+        assert_eq!(balance(&db_conn, &bob).unwrap(), nano("2"));
+        assert_eq!(balance(&db_conn, &user_account(3)).unwrap(), Amount::default());
+        assert!(check(&db_conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn books_the_same_deposit_once() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+        let alice: String = user_account(1);
+        let posting = Posting {
+            debit: EXTERNAL,
+            credit: &alice,
+            amount: nano("1"),
+            kind: DEPOSIT,
+            reference: "A",
+        };
+
+        let first: i64 = post(&db_conn, &posting).expect("deposit");
+
+        assert_eq!(post(&db_conn, &posting).expect("repeated deposit"), first);
+        assert_eq!(balance(&db_conn, &alice).unwrap(), nano("1"));
+        assert!(check(&db_conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_overdrafts_and_duplicates() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+        let (alice, bob) = (user_account(1), user_account(2));
+
+        deposit(&db_conn, &alice, nano("1"), "A");
+
+        let overdraft = post(
+            &db_conn,
+            &Posting {
+                debit: &alice,
+                credit: &bob,
+                amount: nano("2"),
+                kind: TIP,
+                reference: "bob",
+            },
+        );
+        let duplicate = post(
+            &db_conn,
+            &Posting {
+                debit: EXTERNAL,
+                credit: &bob,
+                amount: nano("1"),
+                kind: DEPOSIT,
+                reference: "A",
+            },
+        );
+
+        match overdraft {
+            Err(LedgerError::InsufficientFunds { available, .. }) => {
+                assert_eq!(available, nano("1"))
+            }
+            _ => panic!("overdraft was booked"),
+        }
+        match duplicate {
+            Err(LedgerError::Duplicate { .. }) => (),
+            _ => panic!("a block was credited to two accounts"),
+        }
+
+        assert_eq!(balance(&db_conn, &alice).unwrap(), nano("1"));
+        assert_eq!(balance(&db_conn, &bob).unwrap(), Amount::default());
+        assert!(check(&db_conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn check_finds_tampered_balances() {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+
+        deposit(&db_conn, &user_account(1), nano("1"), "A");
+
+        db_conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE ledger_balances SET credits = '7' WHERE account = 'user:1'", &[])
+            .unwrap();
+
+        assert_eq!(check(&db_conn).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,437 @@
+use chrono::Utc;
+use db::DatabaseError;
+use rusqlite::{Connection, Error};
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<(), Error>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create accounts table",
+        apply: create_accounts_table,
+    },
+    Migration {
+        version: 2,
+        description: "create transactions table",
+        apply: create_transactions_table,
+    },
+    Migration {
+        version: 3,
+        description: "link platform identities to accounts",
+        apply: create_identities_table,
+    },
+    Migration {
+        version: 4,
+        description: "add display currency to accounts",
+        apply: add_account_currency,
+    },
+    Migration {
+        version: 5,
+        description: "create receive failures table",
+        apply: create_receive_failures_table,
+    },
+    Migration {
+        version: 6,
+        description: "store conversation references and sent notifications",
+        apply: create_notification_tables,
+    },
+    Migration {
+        version: 7,
+        description: "create custody ledger tables",
+        apply: create_ledger_tables,
+    },
+    Migration {
+        version: 8,
+        description: "record sweeps until they are credited",
+        apply: create_sweeps_table,
+    },
+    Migration {
+        version: 9,
+        description: "drop identity columns now kept in identities from accounts",
+        apply: drop_account_identity_columns,
+    },
+];
+
+/// Brings the schema up to the latest version, applying each pending migration in its own
+/// transaction and recording it in `schema_migrations`.
+pub fn run(conn: &mut Connection) -> Result<(), DatabaseError> {
+    if let Err(e) = create_metadata_table(conn) {
+        return Err(DatabaseError::Metadata(e));
+    }
+
+    let current: i64 = match current_version(conn) {
+        Ok(v) => v,
+        Err(e) => return Err(DatabaseError::Metadata(e)),
+    };
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        if let Err(e) = apply(conn, migration) {
+            return Err(DatabaseError::Migration {
+                version: migration.version,
+                description: migration.description,
+                cause: e,
+            });
+        }
+
+        println!(
+            "Applied database migration {}: {}",
+            migration.version, migration.description
+        );
+    }
+
+    Ok(())
+}
+
+// Foreign keys are switched off while a migration runs, as SQLite cannot toggle them inside a
+// transaction and rebuilding a referenced table would otherwise fail or cascade.
+fn apply(conn: &mut Connection, migration: &Migration) -> Result<(), Error> {
+    let foreign_keys: i64 = conn.query_row("PRAGMA foreign_keys", &[], |row| row.get(0))?;
+    conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+
+    let applied = apply_in_transaction(conn, migration);
+
+    if foreign_keys != 0 {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    }
+
+    applied
+}
+
+fn apply_in_transaction(conn: &mut Connection, migration: &Migration) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+
+    (migration.apply)(&tx)?;
+
+    tx.execute(
+        "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?1, ?2, ?3)",
+        &[
+            &migration.version,
+            &migration.description,
+            &Utc::now().to_rfc3339(),
+        ],
+    )?;
+
+    tx.commit()
+}
+
+fn create_metadata_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+                  version           INTEGER PRIMARY KEY,
+                  description       TEXT NOT NULL,
+                  applied_at        TEXT NOT NULL
+                  )",
+        &[],
+    )?;
+
+    Ok(())
+}
+
+fn current_version(conn: &Connection) -> Result<i64, Error> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        &[],
+        |row| row.get(0),
+    )
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map(&[], |row| row.get::<_, String>(1))?;
+
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Databases created by hand before migrations existed may already have the accounts table,
+// with or without the teamsId column, so both steps are conditional.
+fn create_accounts_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS accounts (
+                  id                INTEGER PRIMARY KEY,
+                  account           TEXT NOT NULL,
+                  public            TEXT NOT NULL,
+                  private           TEXT NOT NULL,
+                  wallet            TEXT NOT NULL,
+                  email             TEXT UNIQUE,
+                  teamsId           TEXT UNIQUE
+                  )",
+        &[],
+    )?;
+
+    if !has_column(conn, "accounts", "teamsId")? {
+        conn.execute("ALTER TABLE accounts ADD COLUMN teamsId TEXT", &[])?;
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS accounts_teams_id ON accounts (teamsId)",
+            &[],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn create_transactions_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS transactions (
+                  id                INTEGER PRIMARY KEY,
+                  sender            TEXT NOT NULL,
+                  receiver          TEXT NOT NULL,
+                  amount            TEXT NOT NULL,
+                  block             TEXT NOT NULL,
+                  platform          TEXT NOT NULL,
+                  kind              TEXT NOT NULL,
+                  timestamp         TEXT NOT NULL
+                  )",
+        &[],
+    )?;
+
+    Ok(())
+}
+
+fn create_identities_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE identities (
+                  id                INTEGER PRIMARY KEY,
+                  account_id        INTEGER NOT NULL REFERENCES accounts (id),
+                  platform          TEXT NOT NULL,
+                  external_id       TEXT NOT NULL,
+                  UNIQUE (platform, external_id)
+                  );
+         INSERT INTO identities (account_id, platform, external_id)
+                  SELECT id, 'hangouts', email FROM accounts WHERE email IS NOT NULL;
+         INSERT INTO identities (account_id, platform, external_id)
+                  SELECT id, 'teams', teamsId FROM accounts WHERE teamsId IS NOT NULL;
+         CREATE TABLE link_codes (
+                  code              TEXT PRIMARY KEY,
+                  account_id        INTEGER NOT NULL REFERENCES accounts (id),
+                  platform          TEXT NOT NULL,
+                  expires_at        INTEGER NOT NULL
+                  );
+         ALTER TABLE transactions ADD COLUMN sender_account TEXT;
+         ALTER TABLE transactions ADD COLUMN receiver_account TEXT;",
+    )
+}
+
+fn add_account_currency(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch("ALTER TABLE accounts ADD COLUMN currency TEXT;")
+}
+
+fn create_receive_failures_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE receive_failures (
+                  block             TEXT PRIMARY KEY,
+                  account           TEXT NOT NULL,
+                  amount            TEXT NOT NULL,
+                  error             TEXT NOT NULL,
+                  attempts          INTEGER NOT NULL,
+                  last_attempt      TEXT NOT NULL
+                  );",
+    )
+}
+
+fn create_notification_tables(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE conversations (
+                  platform          TEXT NOT NULL,
+                  external_id       TEXT NOT NULL,
+                  conversation      TEXT NOT NULL,
+                  service_url       TEXT NOT NULL,
+                  bot_id            TEXT NOT NULL,
+                  tenant_id         TEXT NOT NULL,
+                  updated_at        TEXT NOT NULL,
+                  PRIMARY KEY (platform, external_id)
+                  );
+         CREATE TABLE notifications (
+                  block             TEXT PRIMARY KEY,
+                  account           TEXT NOT NULL,
+                  created_at        TEXT NOT NULL
+                  );",
+    )
+}
+
+fn create_ledger_tables(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE ledger_entries (
+                  id                INTEGER PRIMARY KEY,
+                  debit             TEXT NOT NULL,
+                  credit            TEXT NOT NULL,
+                  amount            TEXT NOT NULL,
+                  kind              TEXT NOT NULL,
+                  reference         TEXT NOT NULL,
+                  created_at        TEXT NOT NULL,
+                  CHECK (debit <> credit)
+                  );
+         CREATE UNIQUE INDEX ledger_entries_block ON ledger_entries (kind, reference)
+                  WHERE kind IN ('deposit', 'settlement');
+         CREATE TABLE ledger_balances (
+                  account           TEXT PRIMARY KEY,
+                  credits           TEXT NOT NULL,
+                  debits            TEXT NOT NULL
+                  );",
+    )
+}
+
+fn create_sweeps_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE sweeps (
+                  block             TEXT PRIMARY KEY,
+                  account_id        INTEGER NOT NULL,
+                  amount            TEXT NOT NULL,
+                  created_at        TEXT NOT NULL,
+                  credited_at       TEXT
+                  );",
+    )
+}
+
+// `identities` took over email and teamsId in migration 3. SQLite cannot drop columns that carry
+// a UNIQUE constraint, so the table is rebuilt, keeping ids so identities still point at it.
+fn drop_account_identity_columns(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE accounts_rebuilt (
+                  id                INTEGER PRIMARY KEY,
+                  account           TEXT NOT NULL,
+                  public            TEXT NOT NULL,
+                  private           TEXT NOT NULL,
+                  wallet            TEXT NOT NULL,
+                  currency          TEXT
+                  );
+         INSERT INTO accounts_rebuilt (id, account, public, private, wallet, currency)
+                  SELECT id, account, public, private, wallet, currency FROM accounts;
+         DROP TABLE accounts;
+         ALTER TABLE accounts_rebuilt RENAME TO accounts;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows = stmt.query_map(&[], |row| row.get::<_, String>(0)).unwrap();
+
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    fn tables(conn: &Connection) -> Vec<String> {
+        names(
+            conn,
+            "SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name",
+        )
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        let rows = stmt.query_map(&[], |row| row.get::<_, String>(1)).unwrap();
+
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    /// Every table and index as SQLite stores them, to compare whole schemas.
+    fn schema(conn: &Connection) -> Vec<String> {
+        names(
+            conn,
+            "SELECT type || ' ' || name || ': ' || COALESCE(sql, '') FROM sqlite_master
+             ORDER BY type, name",
+        )
+    }
+
+    fn assert_latest(conn: &Connection) {
+        let latest: i64 = MIGRATIONS.last().unwrap().version;
+
+        assert_eq!(current_version(conn).unwrap(), latest);
+        assert_eq!(
+            tables(conn),
+            vec![
+                "accounts",
+                "conversations",
+                "identities",
+                "ledger_balances",
+                "ledger_entries",
+                "link_codes",
+                "notifications",
+                "receive_failures",
+                "schema_migrations",
+                "sweeps",
+                "transactions",
+            ]
+        );
+        assert_eq!(
+            columns(conn, "accounts"),
+            vec!["id", "account", "public", "private", "wallet", "currency"]
+        );
+        assert!(columns(conn, "transactions").contains(&"receiver_account".to_string()));
+    }
+
+    #[test]
+    fn migrates_a_fresh_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        run(&mut conn).expect("migrations");
+
+        assert_latest(&conn);
+    }
+
+    #[test]
+    fn migrates_accounts_without_teams_ids() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE accounts (
+                      id                INTEGER PRIMARY KEY,
+                      account           TEXT NOT NULL,
+                      public            TEXT NOT NULL,
+                      private           TEXT NOT NULL,
+                      wallet            TEXT NOT NULL,
+                      email             TEXT UNIQUE
+                      );
+             INSERT INTO accounts (account, public, private, wallet, email)
+                      VALUES ('nano_1', 'AB', 'CD', 'W', 'alice@example.com');",
+        ).unwrap();
+
+        run(&mut conn).expect("migrations");
+
+        assert_latest(&conn);
+        assert_eq!(
+            names(&conn, "SELECT platform || ' ' || external_id FROM identities"),
+            vec!["hangouts alice@example.com"]
+        );
+        assert_eq!(
+            names(
+                &conn,
+                "SELECT accounts.account FROM accounts
+                 INNER JOIN identities ON identities.account_id = accounts.id"
+            ),
+            vec!["nano_1"]
+        );
+        assert!(!schema(&conn).iter().any(|s| s.contains("accounts_rebuilt")));
+    }
+
+    #[test]
+    fn running_twice_changes_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        run(&mut conn).expect("migrations");
+        let (first, applied): (Vec<String>, Vec<String>) = (
+            schema(&conn),
+            names(&conn, "SELECT applied_at FROM schema_migrations ORDER BY version"),
+        );
+
+        run(&mut conn).expect("migrations again");
+
+        assert_eq!(schema(&conn), first);
+        assert_eq!(
+            names(&conn, "SELECT applied_at FROM schema_migrations ORDER BY version"),
+            applied
+        );
+        assert_eq!(applied.len(), MIGRATIONS.len());
+    }
+}
@@ -0,0 +1,562 @@
+mod cipher;
+pub mod ledger;
+mod migrations;
+
+pub use self::cipher::{CipherError, KeyCipher};
+
+use node::{Account, Key};
+use rusqlite::{Connection, Error};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Directory(io::Error),
+    Open(Error),
+    Metadata(Error),
+    Migration {
+        version: i64,
+        description: &'static str,
+        cause: Error,
+    },
+}
+
+pub struct LinkCode {
+    pub account_id: i64,
+    pub platform: String,
+    pub expires_at: i64,
+}
+
+/// Where to reach a user outside of a reply. For Hangouts `conversation` is the name of the direct
+/// message space; Teams also needs the service, bot and tenant to open a personal conversation
+/// when `conversation` is empty.
+pub struct ConversationReference {
+    pub platform: String,
+    pub external_id: String,
+    pub conversation: String,
+    pub service_url: String,
+    pub bot_id: String,
+    pub tenant_id: String,
+}
+
+pub struct ReceiveFailure {
+    pub block: String,
+    pub account: String,
+    pub amount: String,
+    pub error: String,
+    pub attempts: i64,
+    pub last_attempt: String,
+}
+
+pub struct Transaction {
+    pub sender: String,
+    pub sender_account: String,
+    pub receiver: String,
+    pub receiver_account: String,
+    pub amount: String,
+    pub block: String,
+    pub platform: String,
+    pub kind: String,
+    pub timestamp: String,
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DatabaseError::Directory(ref e) => {
+                write!(f, "Could not create the database directory: {}", e)
+            }
+            DatabaseError::Open(ref e) => write!(f, "Could not open the database: {}", e),
+            DatabaseError::Metadata(ref e) => {
+                write!(f, "Could not read the schema version: {}", e)
+            }
+            DatabaseError::Migration {
+                version,
+                description,
+                ref cause,
+            } => write!(
+                f,
+                "Database migration {} ({}) failed: {}",
+                version, description, cause
+            ),
+        }
+    }
+}
+
+impl error::Error for DatabaseError {
+    fn description(&self) -> &str {
+        "database error"
+    }
+}
+
+pub fn add_account(
+    db_conn: &Mutex<Connection>,
+    key: &Key,
+    wallet: &str,
+    platform: &str,
+    external_id: &str,
+) -> Result<i64, Error> {
+    let mut conn = db_conn.lock().expect("db connection lock");
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "INSERT INTO accounts (account, public, private, wallet) VALUES (?1, ?2, ?3, ?4)",
+        &[&key.account, &key.public, &key.private, &wallet],
+    )?;
+
+    let account_id: i64 = tx.last_insert_rowid();
+
+    tx.execute(
+        "INSERT INTO identities (account_id, platform, external_id) VALUES (?1, ?2, ?3)",
+        &[&account_id, &platform, &external_id],
+    )?;
+
+    tx.commit()?;
+
+    Ok(account_id)
+}
+
+pub fn get_account(
+    db_conn: &Mutex<Connection>,
+    platform: &str,
+    external_id: &str,
+) -> Result<Account, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT accounts.id, account, public, private, wallet FROM accounts
+         INNER JOIN identities ON identities.account_id = accounts.id
+         WHERE identities.platform = ?1 AND identities.external_id = ?2",
+        &[&platform, &external_id],
+        |row| Account {
+            id: row.get(0),
+            account: row.get(1),
+            public: row.get(2),
+            private: row.get(3),
+            wallet: row.get(4),
+        },
+    )
+}
+
+pub fn get_all_accounts(db_conn: &Mutex<Connection>) -> Result<Vec<Account>, Error> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let mut stmt = conn.prepare("SELECT id, account, public, private, wallet FROM accounts")?;
+    let rows = stmt.query_map(&[], |row| Account {
+        id: row.get(0),
+        account: row.get(1),
+        public: row.get(2),
+        private: row.get(3),
+        wallet: row.get(4),
+    })?;
+
+    let mut accounts: Vec<Account> = Vec::new();
+
+    for account in rows {
+        accounts.push(account?);
+    }
+
+    Ok(accounts)
+}
+
+pub fn link_identity(
+    db_conn: &Mutex<Connection>,
+    account_id: i64,
+    platform: &str,
+    external_id: &str,
+) -> Result<i32, Error> {
+    db_conn.lock().expect("db connection lock").execute(
+        "INSERT OR REPLACE INTO identities (account_id, platform, external_id) VALUES (?1, ?2, ?3)",
+        &[&account_id, &platform, &external_id],
+    )
+}
+
+/// Display currency chosen by the account owner, `None` until one is set.
+pub fn get_currency(db_conn: &Mutex<Connection>, account_id: i64) -> Result<Option<String>, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT currency FROM accounts WHERE id = ?",
+        &[&account_id],
+        |row| row.get(0),
+    )
+}
+
+pub fn set_currency(
+    db_conn: &Mutex<Connection>,
+    account_id: i64,
+    currency: &str,
+) -> Result<i32, Error> {
+    db_conn.lock().expect("db connection lock").execute(
+        "UPDATE accounts SET currency = ?1 WHERE id = ?2",
+        &[&currency, &account_id],
+    )
+}
+
+pub fn add_link_code(
+    db_conn: &Mutex<Connection>,
+    code: &str,
+    account_id: i64,
+    platform: &str,
+    expires_at: i64,
+) -> Result<i32, Error> {
+    db_conn.lock().expect("db connection lock").execute(
+        "INSERT OR REPLACE INTO link_codes (code, account_id, platform, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        &[&code, &account_id, &platform, &expires_at],
+    )
+}
+
+pub fn get_link_code(db_conn: &Mutex<Connection>, code: &str) -> Result<LinkCode, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT account_id, platform, expires_at FROM link_codes WHERE code = ?",
+        &[&code],
+        |row| LinkCode {
+            account_id: row.get(0),
+            platform: row.get(1),
+            expires_at: row.get(2),
+        },
+    )
+}
+
+/// Consumes `code`, returning `false` when it was already used.
+pub fn take_link_code(db_conn: &Mutex<Connection>, code: &str) -> Result<bool, Error> {
+    let deleted: i32 = db_conn
+        .lock()
+        .expect("db connection lock")
+        .execute("DELETE FROM link_codes WHERE code = ?", &[&code])?;
+
+    Ok(deleted > 0)
+}
+
+pub fn add_transaction(
+    db_conn: &Mutex<Connection>,
+    transaction: &Transaction,
+) -> Result<i32, Error> {
+    db_conn.lock().expect("db connection lock").execute(
+        "INSERT INTO transactions (sender, sender_account, receiver, receiver_account, amount,
+                                   block, platform, kind, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        &[
+            &transaction.sender,
+            &transaction.sender_account,
+            &transaction.receiver,
+            &transaction.receiver_account,
+            &transaction.amount,
+            &transaction.block,
+            &transaction.platform,
+            &transaction.kind,
+            &transaction.timestamp,
+        ],
+    )
+}
+
+pub fn get_transactions(
+    db_conn: &Mutex<Connection>,
+    account: &str,
+    platform: &str,
+    user_id: &str,
+    limit: i64,
+) -> Result<Vec<Transaction>, Error> {
+    let conn = db_conn.lock().expect("db connection lock");
+    // Rows written before identities were linked only carry the platform user id
+    let mut stmt = conn.prepare(
+        "SELECT sender, COALESCE(sender_account, ''), receiver, COALESCE(receiver_account, ''),
+                amount, block, platform, kind, timestamp FROM transactions
+         WHERE sender_account = ?1 OR receiver_account = ?1
+            OR (platform = ?2 AND (sender = ?3 OR receiver = ?3))
+         ORDER BY id DESC LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(&[&account, &platform, &user_id, &limit], |row| Transaction {
+        sender: row.get(0),
+        sender_account: row.get(1),
+        receiver: row.get(2),
+        receiver_account: row.get(3),
+        amount: row.get(4),
+        block: row.get(5),
+        platform: row.get(6),
+        kind: row.get(7),
+        timestamp: row.get(8),
+    })?;
+
+    let mut transactions: Vec<Transaction> = Vec::new();
+
+    for transaction in rows {
+        transactions.push(transaction?);
+    }
+
+    Ok(transactions)
+}
+
+/// Records a pending block that could not be received, counting repeated attempts.
+pub fn add_receive_failure(
+    db_conn: &Mutex<Connection>,
+    block: &str,
+    account: &str,
+    amount: &str,
+    error: &str,
+    timestamp: &str,
+) -> Result<i32, Error> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let updated: i32 = conn.execute(
+        "UPDATE receive_failures SET error = ?1, attempts = attempts + 1, last_attempt = ?2
+         WHERE block = ?3",
+        &[&error, &timestamp, &block],
+    )?;
+
+    if updated > 0 {
+        return Ok(updated);
+    }
+
+    conn.execute(
+        "INSERT INTO receive_failures (block, account, amount, error, attempts, last_attempt)
+         VALUES (?1, ?2, ?3, ?4, 1, ?5)",
+        &[&block, &account, &amount, &error, &timestamp],
+    )
+}
+
+pub fn remove_receive_failure(db_conn: &Mutex<Connection>, block: &str) -> Result<i32, Error> {
+    db_conn
+        .lock()
+        .expect("db connection lock")
+        .execute("DELETE FROM receive_failures WHERE block = ?", &[&block])
+}
+
+pub fn get_receive_failures(db_conn: &Mutex<Connection>) -> Result<Vec<ReceiveFailure>, Error> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let mut stmt = conn.prepare(
+        "SELECT block, account, amount, error, attempts, last_attempt FROM receive_failures
+         ORDER BY last_attempt DESC",
+    )?;
+    let rows = stmt.query_map(&[], |row| ReceiveFailure {
+        block: row.get(0),
+        account: row.get(1),
+        amount: row.get(2),
+        error: row.get(3),
+        attempts: row.get(4),
+        last_attempt: row.get(5),
+    })?;
+
+    let mut failures: Vec<ReceiveFailure> = Vec::new();
+
+    for failure in rows {
+        failures.push(failure?);
+    }
+
+    Ok(failures)
+}
+
+pub fn save_conversation(
+    db_conn: &Mutex<Connection>,
+    reference: &ConversationReference,
+    timestamp: &str,
+) -> Result<i32, Error> {
+    db_conn.lock().expect("db connection lock").execute(
+        "INSERT OR REPLACE INTO conversations
+            (platform, external_id, conversation, service_url, bot_id, tenant_id, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        &[
+            &reference.platform,
+            &reference.external_id,
+            &reference.conversation,
+            &reference.service_url,
+            &reference.bot_id,
+            &reference.tenant_id,
+            &timestamp,
+        ],
+    )
+}
+
+/// Conversations of every identity linked to the owner of `account`.
+pub fn get_conversations(
+    db_conn: &Mutex<Connection>,
+    account: &str,
+) -> Result<Vec<ConversationReference>, Error> {
+    let conn = db_conn.lock().expect("db connection lock");
+    let mut stmt = conn.prepare(
+        "SELECT conversations.platform, conversations.external_id, conversation, service_url,
+                bot_id, tenant_id FROM conversations
+         INNER JOIN identities ON identities.platform = conversations.platform
+                              AND identities.external_id = conversations.external_id
+         INNER JOIN accounts ON accounts.id = identities.account_id
+         WHERE accounts.account = ?",
+    )?;
+    let rows = stmt.query_map(&[&account], |row| ConversationReference {
+        platform: row.get(0),
+        external_id: row.get(1),
+        conversation: row.get(2),
+        service_url: row.get(3),
+        bot_id: row.get(4),
+        tenant_id: row.get(5),
+    })?;
+
+    let mut references: Vec<ConversationReference> = Vec::new();
+
+    for reference in rows {
+        references.push(reference?);
+    }
+
+    Ok(references)
+}
+
+/// Claims the notification for `block`, `false` when it was already sent.
+pub fn add_notification(
+    db_conn: &Mutex<Connection>,
+    block: &str,
+    account: &str,
+    timestamp: &str,
+) -> Result<bool, Error> {
+    let inserted: i32 = db_conn.lock().expect("db connection lock").execute(
+        "INSERT OR IGNORE INTO notifications (block, account, created_at) VALUES (?1, ?2, ?3)",
+        &[&block, &account, &timestamp],
+    )?;
+
+    Ok(inserted > 0)
+}
+
+pub fn count_notifications(db_conn: &Mutex<Connection>, account: &str) -> Result<i64, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT COUNT(*) FROM notifications WHERE account = ?",
+        &[&account],
+        |row| row.get(0),
+    )
+}
+
+pub fn is_bot_account(db_conn: &Mutex<Connection>, account: &str) -> Result<bool, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT COUNT(*) FROM accounts WHERE account = ?",
+        &[&account],
+        |row| row.get::<_, i64>(0) > 0,
+    )
+}
+
+pub fn count_plaintext_keys(db_conn: &Mutex<Connection>) -> Result<i64, Error> {
+    db_conn.lock().expect("db connection lock").query_row(
+        "SELECT COUNT(*) FROM accounts WHERE private NOT LIKE 'enc1:%'",
+        &[],
+        |row| row.get(0),
+    )
+}
+
+/// Seals every private key still stored in plaintext, all in one transaction.
+pub fn encrypt_private_keys(
+    db_conn: &Mutex<Connection>,
+    cipher: &KeyCipher,
+) -> Result<usize, Box<error::Error>> {
+    let mut conn = db_conn.lock().expect("db connection lock");
+    let tx = conn.transaction()?;
+
+    let plaintext: Vec<(i64, String, String)> = {
+        let mut stmt =
+            tx.prepare("SELECT id, account, private FROM accounts WHERE private NOT LIKE 'enc1:%'")?;
+        let rows = stmt.query_map(&[], |row| -> (i64, String, String) {
+            (row.get(0), row.get(1), row.get(2))
+        })?;
+
+        let mut keys = Vec::new();
+
+        for row in rows {
+            keys.push(row?);
+        }
+
+        keys
+    };
+
+    for &(id, ref account, ref private) in &plaintext {
+        let sealed: String = cipher.encrypt(account, private)?;
+
+        tx.execute(
+            "UPDATE accounts SET private = ?1 WHERE id = ?2",
+            &[&sealed, &id],
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(plaintext.len())
+}
+
+pub fn get_connection(path: &str) -> Result<Mutex<Connection>, DatabaseError> {
+    let path = Path::new(path);
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(DatabaseError::Directory(e));
+        }
+    }
+
+    let mut conn = match Connection::open(path) {
+        Ok(c) => c,
+        Err(e) => return Err(DatabaseError::Open(e)),
+    };
+
+    migrations::run(&mut conn)?;
+
+    Ok(Mutex::new(conn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(sender: &str, receiver: &str, block: &str) -> Transaction {
+        Transaction {
+            sender: sender.to_string(),
+            sender_account: format!("nano_{}", sender),
+            receiver: receiver.to_string(),
+            receiver_account: format!("nano_{}", receiver),
+            amount: "1".to_string(),
+            block: block.to_string(),
+            platform: "hangouts".to_string(),
+            kind: "tip".to_string(),
+            timestamp: "2018-06-01T12:00:00+00:00".to_string(),
+        }
+    }
+
+    fn blocks(transactions: Vec<Transaction>) -> Vec<String> {
+        transactions.into_iter().map(|t| t.block).collect()
+    }
+
+    #[test]
+    fn lists_transactions_of_an_account_newest_first() {
+        let db_conn = get_connection(":memory:").expect("in-memory database");
+
+        for &(sender, receiver, block) in &[
+            ("alice", "bob", "A"),
+            ("bob", "carol", "B"),
+            ("carol", "alice", "C"),
+        ] {
+            add_transaction(&db_conn, &transaction(sender, receiver, block)).expect("transaction");
+        }
+
+        let alice = get_transactions(&db_conn, "nano_alice", "hangouts", "alice", 10).unwrap();
+        assert_eq!(blocks(alice), vec!["C", "A"]);
+
+        let limited = get_transactions(&db_conn, "nano_bob", "hangouts", "bob", 1).unwrap();
+        assert_eq!(blocks(limited), vec!["B"]);
+
+        let dave = get_transactions(&db_conn, "nano_dave", "hangouts", "dave", 10).unwrap();
+        assert!(dave.is_empty());
+    }
+
+    #[test]
+    fn finds_rows_written_before_accounts_were_recorded() {
+        let db_conn = get_connection(":memory:").expect("in-memory database");
+
+        db_conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO transactions (sender, receiver, amount, block, platform, kind,
+                                           timestamp)
+                 VALUES ('alice', 'bob', '1', 'OLD', 'hangouts', 'tip', '2018-01-01')",
+                &[],
+            )
+            .unwrap();
+
+        let alice = get_transactions(&db_conn, "nano_alice", "hangouts", "alice", 10).unwrap();
+        let on_teams = get_transactions(&db_conn, "nano_alice", "teams", "alice", 10).unwrap();
+
+        assert_eq!(alice.len(), 1);
+        assert_eq!(alice[0].sender_account, "");
+        assert!(on_teams.is_empty());
+    }
+}
@@ -10,21 +10,209 @@ extern crate serde_json;
 #[macro_use]
 extern crate erased_serde;
 
+#[macro_use]
+extern crate lazy_static;
+
+extern crate base64;
+extern crate blake2;
 extern crate chrono;
+extern crate ed25519_dalek;
 extern crate futures;
+extern crate hex;
 extern crate hyper;
 extern crate hyper_tls;
+extern crate rand;
 extern crate regex;
+extern crate ring;
 extern crate rocket;
 extern crate rocket_contrib;
 extern crate rusqlite;
 extern crate serde;
 extern crate tokio_core;
+extern crate toml;
+extern crate tungstenite;
+extern crate untrusted;
+extern crate url;
 
 mod api;
+mod config;
+mod custody;
 mod db;
 mod node;
+mod worker;
+
+use std::env;
+use std::process;
+use std::time::Duration;
 
 fn main() {
-    api::controller::rocket(db::get_connection()).launch();
+    let rocket = rocket::ignite();
+
+    let config = match config::Config::load(rocket.config()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let db_conn = match db::get_connection(&config.database.path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let cipher = match db::KeyCipher::from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if env::args().nth(1) == Some("receive-failures".to_string()) {
+        match db::get_receive_failures(&db_conn) {
+            Ok(failures) => {
+                for f in &failures {
+                    println!(
+                        "{} {} raw into {}, {} attempts, last at {}: {}",
+                        f.block, f.amount, f.account, f.attempts, f.last_attempt, f.error
+                    );
+                }
+
+                println!("{} pending blocks could not be received", failures.len());
+            }
+            Err(e) => {
+                eprintln!("Could not read receive failures: {}", e);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    if env::args().nth(1) == Some("encrypt-keys".to_string()) {
+        match db::encrypt_private_keys(&db_conn, &cipher) {
+            Ok(count) => println!("Encrypted {} private keys", count),
+            Err(e) => {
+                eprintln!("Could not encrypt private keys: {}", e);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    match db::count_plaintext_keys(&db_conn) {
+        Ok(0) => (),
+        Ok(count) => println!(
+            "Warning: {} private keys are stored unencrypted, run `rusty_nanobot encrypt-keys`",
+            count
+        ),
+        Err(e) => println!("Could not check private key encryption: {}", e),
+    }
+
+    let node = match node::NanoRpcClient::new(node::RpcConfig {
+        url: config.node.url.to_owned(),
+        timeout: Duration::from_secs(config.node.timeout_secs),
+        ..node::RpcConfig::default()
+    }) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if env::args().nth(1) == Some("reconcile".to_string()) {
+        match custody::reconcile(&db_conn, &node) {
+            Ok(report) => {
+                print!("{}", report);
+
+                if !report.is_healthy() {
+                    process::exit(2);
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not reconcile the ledger: {}", e);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    if config.custody.enabled {
+        let signer = node::Signer::new(&node, &cipher, &config.node);
+
+        match custody::open_hot_wallet(&db_conn, &signer, &cipher) {
+            Ok(hot) => println!("Custody is on, deposits are swept into {}", hot.account),
+            Err(e) => {
+                eprintln!("Could not open the hot wallet: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if config.receiver.enabled {
+        let interval = Duration::from_secs(config.receiver.interval_secs);
+        let receiver = match (
+            db::get_connection(&config.database.path),
+            db::KeyCipher::from_env(),
+        ) {
+            (Ok(c), Ok(k)) => worker::Receiver::new(
+                c,
+                node.clone(),
+                k,
+                config.node.clone(),
+                &config.receiver,
+                config.custody.enabled,
+            ),
+            (Err(e), _) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            (_, Err(e)) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+
+        if let Err(e) = receiver.spawn(interval) {
+            eprintln!("Could not start the receiver: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if config.notifications.enabled {
+        let notifiers = api::notify::from_config(&config);
+
+        if notifiers.is_empty() {
+            println!("Deposit notifications are off, no platform is able to message users");
+        } else {
+            let interval = Duration::from_secs(config.notifications.poll_interval_secs);
+            let watcher = match db::get_connection(&config.database.path) {
+                Ok(c) => worker::DepositWatcher::new(
+                    c,
+                    node.clone(),
+                    notifiers,
+                    config.node.websocket_url.to_owned(),
+                    &config.notifications,
+                ),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            if let Err(e) = watcher.spawn(interval) {
+                eprintln!("Could not start the deposit watcher: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    api::controller::rocket(rocket, config, db_conn, node, cipher).launch();
 }
@@ -0,0 +1,270 @@
+use blake2::digest::{Input, VariableOutput};
+use blake2::VarBlake2b;
+use hex;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Nano's base32 alphabet, which leaves out 0, 2, l and v.
+const ALPHABET: &[u8] = b"13456789abcdefghijkmnopqrstuwxyz";
+
+/// `xrb_` is the legacy prefix and the dashed forms predate both. All are read, `nano_` is
+/// written.
+const PREFIXES: &[&str] = &["nano_", "xrb_", "nano-", "xrb-"];
+const PREFIX: &str = "nano_";
+
+/// 4 padding bits and a 256 bit key make 52 characters, a 40 bit checksum makes 8 more.
+const KEY_CHARS: usize = 52;
+const CHECKSUM_CHARS: usize = 8;
+const KEY_PADDING_BITS: usize = 4;
+
+/// An account address, held as the public key it encodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NanoAddress([u8; 32]);
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError {
+    Prefix,
+    Length(usize),
+    Character(char),
+    Padding,
+    Checksum,
+    PublicKey(String),
+}
+
+impl NanoAddress {
+    /// Reads `nano_` and `xrb_` addresses in any case, checking the checksum.
+    pub fn parse(address: &str) -> Result<NanoAddress, AddressError> {
+        let address: String = address.trim().to_lowercase();
+
+        let encoded: &str = match PREFIXES.iter().find(|p| address.starts_with(*p)) {
+            Some(prefix) => &address[prefix.len()..],
+            None => return Err(AddressError::Prefix),
+        };
+
+        if encoded.len() != KEY_CHARS + CHECKSUM_CHARS || !encoded.is_ascii() {
+            return Err(AddressError::Length(encoded.chars().count()));
+        }
+
+        let mut key = [0u8; 32];
+        decode(&encoded[..KEY_CHARS], KEY_PADDING_BITS, &mut key)?;
+
+        let mut checksum = [0u8; 5];
+        decode(&encoded[KEY_CHARS..], 0, &mut checksum)?;
+
+        if checksum != NanoAddress(key).checksum() {
+            return Err(AddressError::Checksum);
+        }
+
+        Ok(NanoAddress(key))
+    }
+
+    /// Address of a hex public key, such as `Key.public`.
+    pub fn from_public_key(public_key: &str) -> Result<NanoAddress, AddressError> {
+        let bytes: Vec<u8> = match hex::decode(public_key.trim()) {
+            Ok(b) => b,
+            Err(e) => return Err(AddressError::PublicKey(e.to_string())),
+        };
+
+        if bytes.len() != 32 {
+            return Err(AddressError::PublicKey(format!(
+                "expected 32 bytes, got {}",
+                bytes.len()
+            )));
+        }
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+
+        Ok(NanoAddress(key))
+    }
+
+    /// Public key as upper case hex, the way the node writes it.
+    pub fn public_key(&self) -> String {
+        hex::encode_upper(&self.0[..])
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Blake2b-40 of the key, in reverse byte order.
+    fn checksum(&self) -> [u8; 5] {
+        let mut hasher = VarBlake2b::new(5).expect("blake2b accepts 5 byte output");
+        hasher.input(&self.0[..]);
+
+        let mut checksum = [0u8; 5];
+        hasher.variable_result(|result| checksum.copy_from_slice(result));
+        checksum.reverse();
+
+        checksum
+    }
+}
+
+impl fmt::Display for NanoAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            PREFIX,
+            encode(&self.0, KEY_PADDING_BITS),
+            encode(&self.checksum(), 0)
+        )
+    }
+}
+
+impl FromStr for NanoAddress {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<NanoAddress, AddressError> {
+        NanoAddress::parse(address)
+    }
+}
+
+impl Serialize for NanoAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for NanoAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NanoAddress, D::Error> {
+        let address: String = String::deserialize(deserializer)?;
+
+        NanoAddress::parse(&address).map_err(de::Error::custom)
+    }
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddressError::Prefix => write!(f, "a Nano address starts with nano_"),
+            AddressError::Length(length) => write!(
+                f,
+                "expected {} characters after the prefix, got {}",
+                KEY_CHARS + CHECKSUM_CHARS,
+                length
+            ),
+            AddressError::Character(c) => write!(f, "`{}` does not belong in a Nano address", c),
+            AddressError::Padding => write!(f, "the address does not encode a public key"),
+            AddressError::Checksum => write!(f, "the checksum does not match, check for typos"),
+            AddressError::PublicKey(ref e) => write!(f, "invalid public key: {}", e),
+        }
+    }
+}
+
+impl Error for AddressError {
+    fn description(&self) -> &str {
+        "invalid nano address"
+    }
+}
+
+/// Base32 of `padding` zero bits followed by `bytes`, five bits per character.
+fn encode(bytes: &[u8], padding: usize) -> String {
+    let bits: usize = padding + bytes.len() * 8;
+
+    (0..bits / 5)
+        .map(|i| {
+            let value: usize = (0..5).fold(0, |value, b| {
+                let position: usize = i * 5 + b;
+                let bit: u8 = if position < padding {
+                    0
+                } else {
+                    let p = position - padding;
+                    (bytes[p / 8] >> (7 - p % 8)) & 1
+                };
+
+                (value << 1) | bit as usize
+            });
+
+            ALPHABET[value] as char
+        })
+        .collect()
+}
+
+/// Reverses `encode`, refusing set padding bits.
+fn decode(encoded: &str, padding: usize, out: &mut [u8]) -> Result<(), AddressError> {
+    for (i, c) in encoded.chars().enumerate() {
+        let value: usize = match ALPHABET.iter().position(|&a| a as char == c) {
+            Some(v) => v,
+            None => return Err(AddressError::Character(c)),
+        };
+
+        for b in 0..5 {
+            let bit: u8 = ((value >> (4 - b)) & 1) as u8;
+            let position: usize = i * 5 + b;
+
+            if position < padding {
+                if bit != 0 {
+                    return Err(AddressError::Padding);
+                }
+            } else {
+                let p = position - padding;
+                out[p / 8] |= bit << (7 - p % 8);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_KEY: &str = "E89208DD038FBB269987689621D52292AE9C35941A7484756ECCED92A65093BA";
+    const GENESIS: &str = "nano_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3";
+
+    #[test]
+    fn round_trips_public_keys() {
+        let cases: &[(&str, &str)] = &[
+            (GENESIS_KEY, GENESIS),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "nano_1111111111111111111111111111111111111111111111111111hifc8npp",
+            ),
+        ];
+
+        for &(key, address) in cases {
+            let from_key = NanoAddress::from_public_key(key).expect("valid key");
+
+            assert_eq!(from_key.to_string(), address);
+            assert_eq!(NanoAddress::parse(address), Ok(from_key));
+            assert_eq!(from_key.public_key(), key);
+        }
+    }
+
+    #[test]
+    fn normalizes_prefixes() {
+        for address in &[
+            "xrb_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3",
+            "xrb-3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3",
+            "NANO_3T6K35GI95XU6TERGT6P69CK76OGMITSA8MNIJTPXM9FKCM736XTONCUOHR3",
+        ] {
+            assert_eq!(
+                NanoAddress::parse(address).map(|a| a.to_string()),
+                Ok(GENESIS.to_string()),
+                "{}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        let body: &str = &GENESIS[5..];
+        let cases: Vec<(String, AddressError)> = vec![
+            (format!("btc_{}", body), AddressError::Prefix),
+            (format!("nano_{}", &body[..58]), AddressError::Length(58)),
+            (format!("nano_{}0", &body[..59]), AddressError::Character('0')),
+            (format!("nano_9{}", &body[1..]), AddressError::Padding),
+            (format!("nano_{}4", &body[..59]), AddressError::Checksum),
+        ];
+
+        for (address, error) in cases {
+            assert_eq!(NanoAddress::parse(&address), Err(error), "{}", address);
+        }
+    }
+}
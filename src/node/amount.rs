@@ -0,0 +1,425 @@
+use api::price::SUPPORTED_CURRENCIES;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+
+/// Number of raw in one NANO (also called Mnano).
+pub const RAW_PER_NANO: u128 = 1_000_000_000_000_000_000_000_000_000_000;
+
+const NANO_EXPONENT: u32 = 30;
+const KNANO_EXPONENT: u32 = 27;
+const DISPLAY_DECIMALS: u32 = 6;
+/// NANO decimals kept when converting from fiat, well past what a fiat cent is worth.
+const FIAT_DECIMALS: u32 = 12;
+const FIAT_SYMBOLS: &[(char, &str)] = &[('€', "EUR"), ('$', "USD"), ('£', "GBP"), ('¥', "JPY")];
+
+/// An amount of Nano, held in raw. The node speaks raw as a decimal string, which is what the
+/// serde impls read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Amount(u128);
+
+/// Amount as typed by a user; fiat values only become an `Amount` once a price is known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmountInput {
+    Nano(Amount),
+    Fiat { currency: String, value: f64 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AmountError {
+    Empty,
+    Invalid(String),
+    TooPrecise(String),
+    Overflow,
+    Zero,
+}
+
+impl Amount {
+    pub fn from_raw(raw: u128) -> Amount {
+        Amount(raw)
+    }
+
+    pub fn raw(&self) -> u128 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(&self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(&self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// Parses a plain integer string of raw, as returned by the node.
+    pub fn from_raw_str(raw: &str) -> Result<Amount, AmountError> {
+        Amount::from_decimal(raw, 0)
+    }
+
+    /// Parses a decimal number of NANO, such as `0.000001`.
+    pub fn from_nano_str(nano: &str) -> Result<Amount, AmountError> {
+        Amount::from_decimal(nano, NANO_EXPONENT)
+    }
+
+    /// Parses `value` scaled by `10^exponent` raw, refusing anything finer than one raw.
+    fn from_decimal(value: &str, exponent: u32) -> Result<Amount, AmountError> {
+        let value = value.trim();
+
+        if value.is_empty() {
+            return Err(AmountError::Empty);
+        }
+
+        let (integer, fraction) = match value.find('.') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => (value, ""),
+        };
+
+        if (integer.is_empty() && fraction.is_empty())
+            || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(AmountError::Invalid(value.to_string()));
+        }
+
+        let fraction = fraction.trim_right_matches('0');
+
+        if fraction.len() as u32 > exponent {
+            return Err(AmountError::TooPrecise(value.to_string()));
+        }
+
+        let scale: u128 = 10u128.pow(exponent);
+        let integer_raw: u128 = if integer.is_empty() {
+            0
+        } else {
+            match integer.parse::<u128>() {
+                Ok(i) => i.checked_mul(scale).ok_or(AmountError::Overflow)?,
+                Err(_) => return Err(AmountError::Overflow),
+            }
+        };
+        let fraction_raw: u128 = if fraction.is_empty() {
+            0
+        } else {
+            // At most 30 digits, which always fits
+            fraction.parse::<u128>().unwrap_or(0) * 10u128.pow(exponent - fraction.len() as u32)
+        };
+
+        match integer_raw.checked_add(fraction_raw) {
+            Some(raw) => Ok(Amount(raw)),
+            None => Err(AmountError::Overflow),
+        }
+    }
+
+    /// Converts a fiat value at `price` per NANO, rounding down to whole raw.
+    pub fn from_fiat(value: f64, price: f64) -> Result<Amount, AmountError> {
+        if price <= 0.0 || !price.is_finite() || !value.is_finite() {
+            return Err(AmountError::Invalid(value.to_string()));
+        }
+
+        let units: f64 = (value / price * 10f64.powi(FIAT_DECIMALS as i32)).floor();
+
+        if units < 0.0 {
+            return Err(AmountError::Invalid(value.to_string()));
+        }
+
+        if units == 0.0 {
+            return Err(AmountError::Zero);
+        }
+
+        if units >= u128::max_value() as f64 {
+            return Err(AmountError::Overflow);
+        }
+
+        match (units as u128).checked_mul(10u128.pow(NANO_EXPONENT - FIAT_DECIMALS)) {
+            Some(raw) => Ok(Amount(raw)),
+            None => Err(AmountError::Overflow),
+        }
+    }
+
+    /// Value of this amount at `price` per NANO.
+    pub fn to_fiat(&self, price: f64) -> f64 {
+        let whole: f64 = (self.0 / RAW_PER_NANO) as f64;
+        let fraction: f64 = (self.0 % RAW_PER_NANO) as f64 / RAW_PER_NANO as f64;
+
+        (whole + fraction) * price
+    }
+}
+
+/// Formats with a fixed number of NANO decimals, rounding down so a balance is never overstated.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let step: u128 = 10u128.pow(NANO_EXPONENT - DISPLAY_DECIMALS);
+
+        write!(
+            f,
+            "{}.{:0width$} NANO",
+            self.0 / RAW_PER_NANO,
+            (self.0 % RAW_PER_NANO) / step,
+            width = DISPLAY_DECIMALS as usize
+        )
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        let raw: String = String::deserialize(deserializer)?;
+
+        Amount::from_raw_str(&raw).map_err(de::Error::custom)
+    }
+}
+
+impl AmountInput {
+    /// Reads amounts like `0.5`, `1.2 NANO`, `500 knano`, `3 raw`, `€2` or `2 USD`. A bare
+    /// number is NANO; other units must be one of the fiat codes the price feed supports.
+    pub fn parse(text: &str) -> Result<AmountInput, AmountError> {
+        let text: String = text.trim().to_lowercase();
+
+        if text.is_empty() {
+            return Err(AmountError::Empty);
+        }
+
+        for &(symbol, currency) in FIAT_SYMBOLS {
+            if text.starts_with(symbol) {
+                return AmountInput::fiat(currency, &text[symbol.len_utf8()..]);
+            }
+
+            if text.ends_with(symbol) {
+                return AmountInput::fiat(currency, &text[..text.len() - symbol.len_utf8()]);
+            }
+        }
+
+        let split: usize = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or_else(|| text.len());
+        let (number, unit) = (&text[..split], text[split..].trim());
+
+        let amount: Amount = match unit {
+            "" | "nano" | "mnano" => Amount::from_decimal(number, NANO_EXPONENT)?,
+            "knano" => Amount::from_decimal(number, KNANO_EXPONENT)?,
+            "raw" => Amount::from_decimal(number, 0)?,
+            code if SUPPORTED_CURRENCIES.contains(&code.to_uppercase().as_str()) => {
+                return AmountInput::fiat(&code.to_uppercase(), number)
+            }
+            _ => return Err(AmountError::Invalid(text.to_string())),
+        };
+
+        if amount.is_zero() {
+            return Err(AmountError::Zero);
+        }
+
+        Ok(AmountInput::Nano(amount))
+    }
+
+    fn fiat(currency: &str, value: &str) -> Result<AmountInput, AmountError> {
+        let value = value.trim();
+
+        match value.parse::<f64>() {
+            Ok(v) if v > 0.0 && v.is_finite() => Ok(AmountInput::Fiat {
+                currency: currency.to_string(),
+                value: v,
+            }),
+            Ok(_) => Err(AmountError::Zero),
+            Err(_) => Err(AmountError::Invalid(value.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for AmountInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AmountInput::Nano(ref amount) => write!(f, "{}", amount),
+            AmountInput::Fiat {
+                ref currency,
+                value,
+            } => write!(f, "{:.2} {}", value, currency),
+        }
+    }
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AmountError::Empty => write!(f, "No amount supplied"),
+            AmountError::Invalid(ref a) => write!(
+                f,
+                "Could not parse amount `{}`, try `1.5`, `500 knano`, `3 raw`, `€2` or `2 USD`",
+                a
+            ),
+            AmountError::TooPrecise(ref a) => {
+                write!(f, "Amount `{}` is more precise than one raw", a)
+            }
+            AmountError::Overflow => write!(f, "Amount is too large"),
+            AmountError::Zero => write!(f, "Amount must be greater than zero"),
+        }
+    }
+}
+
+impl Error for AmountError {
+    fn description(&self) -> &str {
+        "invalid amount"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nano(input: &str) -> Result<Amount, AmountError> {
+        match AmountInput::parse(input)? {
+            AmountInput::Nano(amount) => Ok(amount),
+            fiat => panic!("{} parsed as {:?}", input, fiat),
+        }
+    }
+
+    fn fiat(input: &str) -> Result<(String, f64), AmountError> {
+        match AmountInput::parse(input)? {
+            AmountInput::Fiat { currency, value } => Ok((currency, value)),
+            amount => panic!("{} parsed as {:?}", input, amount),
+        }
+    }
+
+    #[test]
+    fn converts_units() {
+        let cases: &[(&str, u128)] = &[
+            ("1", RAW_PER_NANO),
+            ("1.5 NANO", RAW_PER_NANO + RAW_PER_NANO / 2),
+            ("2 Mnano", 2 * RAW_PER_NANO),
+            ("500 knano", RAW_PER_NANO / 2),
+            ("0.001knano", 1_000_000_000_000_000_000_000_000),
+            ("3 raw", 3),
+            (".25", RAW_PER_NANO / 4),
+            ("7.", 7 * RAW_PER_NANO),
+        ];
+
+        for &(input, raw) in cases {
+            assert_eq!(nano(input), Ok(Amount::from_raw(raw)), "{}", input);
+        }
+
+        assert_eq!(Amount::from_raw_str("1000").unwrap().raw(), 1000);
+        assert_eq!(Amount::from_nano_str("0.000001").unwrap().raw(), RAW_PER_NANO / 1_000_000);
+    }
+
+    #[test]
+    fn limits_precision_to_one_raw() {
+        let one_raw: &str = "0.000000000000000000000000000001";
+
+        assert_eq!(Amount::from_nano_str(one_raw), Ok(Amount::from_raw(1)));
+        assert_eq!(
+            Amount::from_nano_str(&format!("{}0000", one_raw)),
+            Ok(Amount::from_raw(1))
+        );
+        assert_eq!(
+            Amount::from_nano_str("0.0000000000000000000000000000001"),
+            Err(AmountError::TooPrecise("0.0000000000000000000000000000001".to_string()))
+        );
+        assert_eq!(
+            nano("1.5 raw"),
+            Err(AmountError::TooPrecise("1.5".to_string()))
+        );
+        assert_eq!(nano("0.000000000000000000000000001 knano"), Ok(Amount::from_raw(1)));
+    }
+
+    #[test]
+    fn rounds_down() {
+        let almost_one: Amount = Amount::from_raw(RAW_PER_NANO - 1);
+
+        assert_eq!(almost_one.to_string(), "0.999999 NANO");
+        assert_eq!(Amount::from_raw(1).to_string(), "0.000000 NANO");
+        assert_eq!(Amount::from_raw(RAW_PER_NANO * 12).to_string(), "12.000000 NANO");
+
+        assert_eq!(
+            Amount::from_fiat(1.0, 3.0),
+            Amount::from_nano_str("0.333333333333")
+        );
+        assert_eq!(
+            Amount::from_fiat(2.0, 3.0),
+            Amount::from_nano_str("0.666666666666")
+        );
+        assert_eq!(
+            Amount::from_fiat(1.000_000_000_000_9, 1.0),
+            Amount::from_nano_str("1")
+        );
+        assert_eq!(Amount::from_fiat(0.000_000_000_000_9, 1.0), Err(AmountError::Zero));
+        assert_eq!(Amount::from_fiat(1.0, 1e13), Err(AmountError::Zero));
+        assert_eq!(Amount::from_nano_str("2.5").unwrap().to_fiat(4.0), 10.0);
+    }
+
+    #[test]
+    fn detects_overflow() {
+        let max: Amount = Amount::from_raw(u128::max_value());
+
+        assert_eq!(Amount::from_raw_str(&u128::max_value().to_string()), Ok(max));
+        assert_eq!(
+            Amount::from_raw_str("340282366920938463463374607431768211456"),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            Amount::from_nano_str("340282366.920938463463374607431768211455"),
+            Ok(max)
+        );
+        assert_eq!(
+            Amount::from_nano_str("340282366.920938463463374607431768211456"),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(Amount::from_nano_str("340282367"), Err(AmountError::Overflow));
+        assert_eq!(max.checked_add(Amount::from_raw(1)), None);
+        assert_eq!(Amount::default().checked_sub(Amount::from_raw(1)), None);
+    }
+
+    #[test]
+    fn rejects_negative_and_garbage_input() {
+        let invalid: &[&str] = &[
+            "-1", "- 1", "1e5", "1.2.3", ".", "abc", "1 nanos", "1 xrb2", "0x10", "1,5", "1 xno",
+            "1 NAN", "2 eru", "2 xyz",
+        ];
+
+        for input in invalid {
+            match AmountInput::parse(input) {
+                Err(AmountError::Invalid(_)) => (),
+                other => panic!("{} parsed as {:?}", input, other),
+            }
+        }
+
+        assert_eq!(AmountInput::parse("  "), Err(AmountError::Empty));
+        assert_eq!(AmountInput::parse("0"), Err(AmountError::Zero));
+        assert_eq!(AmountInput::parse("0.000 raw"), Err(AmountError::Zero));
+        assert_eq!(AmountInput::parse("€-2"), Err(AmountError::Zero));
+        assert_eq!(AmountInput::parse("$0"), Err(AmountError::Zero));
+        assert_eq!(AmountInput::parse("€two"), Err(AmountError::Invalid("two".to_string())));
+        assert_eq!(Amount::from_raw_str("-1"), Err(AmountError::Invalid("-1".to_string())));
+        assert_eq!(Amount::from_fiat(1.0, 0.0), Err(AmountError::Invalid("1".to_string())));
+        assert!(Amount::from_fiat(::std::f64::NAN, 1.0).is_err());
+    }
+
+    #[test]
+    fn reads_fiat_symbols_and_codes() {
+        let cases: &[(&str, &str, f64)] = &[
+            ("€2", "EUR", 2.0),
+            ("2€", "EUR", 2.0),
+            ("$1.50", "USD", 1.5),
+            ("£ 3", "GBP", 3.0),
+            ("¥100", "JPY", 100.0),
+            ("2 usd", "USD", 2.0),
+            ("2.5CHF", "CHF", 2.5),
+            ("10 sek", "SEK", 10.0),
+        ];
+
+        for &(input, currency, value) in cases {
+            assert_eq!(fiat(input), Ok((currency.to_string(), value)), "{}", input);
+        }
+
+        assert_eq!(
+            AmountInput::parse("1.5 eur").unwrap().to_string(),
+            "1.50 EUR"
+        );
+    }
+}
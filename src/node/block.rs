@@ -0,0 +1,283 @@
+use blake2::digest::{Input, VariableOutput};
+use blake2::{Blake2b, VarBlake2b};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use hex;
+use node::{Amount, NanoAddress};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::error::Error;
+use std::fmt;
+
+/// Previous block of an account that has not been opened yet.
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Block type tag hashed in front of every state block.
+const STATE_BLOCK_TYPE: u8 = 6;
+
+#[derive(Debug)]
+pub enum SigningError {
+    InvalidField(&'static str),
+    InvalidKey(String),
+    Random,
+}
+
+/// A state block waiting for its signature and work. Hashes and the link are hex, the way the
+/// node reads and writes them.
+pub struct StateBlock {
+    pub account: NanoAddress,
+    pub previous: String,
+    pub representative: NanoAddress,
+    pub balance: Amount,
+    pub link: String,
+}
+
+/// Block as given to the `process` RPC.
+#[derive(Serialize)]
+pub struct SignedBlock {
+    #[serde(rename = "type")]
+    block_type: &'static str,
+
+    account: NanoAddress,
+    previous: String,
+    representative: NanoAddress,
+    balance: Amount,
+    link: String,
+    signature: String,
+    work: String,
+}
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SigningError::InvalidField(field) => write!(f, "Block field {} is not valid", field),
+            SigningError::InvalidKey(ref e) => write!(f, "Invalid private key: {}", e),
+            SigningError::Random => write!(f, "Could not generate a private key"),
+        }
+    }
+}
+
+impl Error for SigningError {
+    fn description(&self) -> &str {
+        "block signing error"
+    }
+}
+
+impl StateBlock {
+    /// Blake2b-256 of the type tag followed by account, previous, representative, balance
+    /// (16 bytes big endian) and link.
+    pub fn hash(&self) -> Result<[u8; 32], SigningError> {
+        let mut preamble = [0u8; 32];
+        preamble[31] = STATE_BLOCK_TYPE;
+
+        let mut hasher = VarBlake2b::new(32).expect("blake2b accepts 32 byte output");
+        hasher.input(&preamble[..]);
+        hasher.input(&self.account.as_bytes()[..]);
+        hasher.input(&decode_32("previous", &self.previous)?[..]);
+        hasher.input(&self.representative.as_bytes()[..]);
+        hasher.input(&u128_to_be(self.balance.raw())[..]);
+        hasher.input(&decode_32("link", &self.link)?[..]);
+
+        let mut hash = [0u8; 32];
+        hasher.variable_result(|result| hash.copy_from_slice(result));
+
+        Ok(hash)
+    }
+
+    /// Signs the block hash with ed25519, using blake2b-512 where ed25519 normally uses SHA-512.
+    pub fn sign(self, private_key: &str, work: String) -> Result<SignedBlock, SigningError> {
+        let keypair: Keypair = keypair(private_key)?;
+
+        if keypair.public.as_bytes() != self.account.as_bytes() {
+            return Err(SigningError::InvalidKey(
+                "it does not belong to the account".to_string(),
+            ));
+        }
+
+        let signature = keypair.sign::<Blake2b>(&self.hash()?);
+
+        Ok(SignedBlock {
+            block_type: "state",
+            account: self.account,
+            previous: self.previous,
+            representative: self.representative,
+            balance: self.balance,
+            link: self.link,
+            signature: hex::encode_upper(&signature.to_bytes()[..]),
+            work,
+        })
+    }
+}
+
+/// A new random private key, as hex.
+pub fn generate_private_key() -> Result<String, SigningError> {
+    let mut private = [0u8; 32];
+
+    if SystemRandom::new().fill(&mut private).is_err() {
+        return Err(SigningError::Random);
+    }
+
+    Ok(hex::encode_upper(&private[..]))
+}
+
+pub fn public_key(private_key: &str) -> Result<String, SigningError> {
+    Ok(hex::encode_upper(keypair(private_key)?.public.as_bytes()))
+}
+
+fn keypair(private_key: &str) -> Result<Keypair, SigningError> {
+    let bytes: Vec<u8> = match hex::decode(private_key) {
+        Ok(b) => b,
+        Err(e) => return Err(SigningError::InvalidKey(e.to_string())),
+    };
+
+    let secret: SecretKey = match SecretKey::from_bytes(&bytes) {
+        Ok(s) => s,
+        Err(e) => return Err(SigningError::InvalidKey(e.to_string())),
+    };
+    let public: PublicKey = PublicKey::from_secret::<Blake2b>(&secret);
+
+    Ok(Keypair { secret, public })
+}
+
+fn decode_32(field: &'static str, value: &str) -> Result<[u8; 32], SigningError> {
+    let bytes: Vec<u8> = match hex::decode(value) {
+        Ok(b) => b,
+        Err(_) => return Err(SigningError::InvalidField(field)),
+    };
+
+    if bytes.len() != 32 {
+        return Err(SigningError::InvalidField(field));
+    }
+
+    let mut decoded = [0u8; 32];
+    decoded.copy_from_slice(&bytes);
+
+    Ok(decoded)
+}
+
+fn u128_to_be(value: u128) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * (15 - i))) as u8;
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signature;
+
+    /// Key 0 of the all zero seed, as published with the Nano key derivation examples.
+    const PRIVATE_KEY: &str = "9F0E444C69F77A49BD0BE89DB92C38FE713E0963165CCA12FAF5712D7657120F";
+    const PUBLIC_KEY: &str = "C008B814A7D269A1FA3C6528B19201A24D797912DB9996FF02A1FF356E45552B";
+    const ADDRESS: &str = "nano_3i1aq1cchnmbn9x5rsbap8b15akfh7wj7pwskuzi7ahz8oq6cobd99d4r3b7";
+
+    /// The live network genesis block, an open block of the genesis account signed by it.
+    const GENESIS_KEY: &str = "E89208DD038FBB269987689621D52292AE9C35941A7484756ECCED92A65093BA";
+    const GENESIS_HASH: &str = "991CF190094C00F0B68E2E5F75F6BEE95A2E0BD93CEAA4A6734DB9F19B728948";
+    const GENESIS_SIGNATURE: &str = concat!(
+        "9F0C933C8ADE004D808EA1985FA746A7E95BA2A38F867640F53EC8F180BDFE9E",
+        "2C1268DEAD7C2664F356E37ABA362BC58E46DBA03E523A7B5A19E4B6EB12BB02"
+    );
+
+    /// State block of the `block_hash` RPC documentation example, with the hash the node returns.
+    const STATE_HASH: &str = "FF0144381CFF0B2C079A115E7ADA7E96F43FD219446E7524C48D1CC9900C4F17";
+
+    fn verify(public_key: &str, message: &[u8], signature: &str) -> bool {
+        let public: PublicKey = PublicKey::from_bytes(&hex::decode(public_key).unwrap()).unwrap();
+        let signature: Signature = Signature::from_bytes(&hex::decode(signature).unwrap()).unwrap();
+
+        public.verify::<Blake2b>(message, &signature).is_ok()
+    }
+
+    fn block(account: NanoAddress) -> StateBlock {
+        StateBlock {
+            account,
+            previous: GENESIS_HASH.to_string(),
+            representative: NanoAddress::from_public_key(GENESIS_KEY).unwrap(),
+            balance: Amount::from_raw(1_000_000_000_000_000_000_000_000_000_000),
+            link: GENESIS_KEY.to_string(),
+        }
+    }
+
+    #[test]
+    fn derives_published_keys() {
+        assert_eq!(public_key(PRIVATE_KEY).unwrap(), PUBLIC_KEY);
+        assert_eq!(NanoAddress::from_public_key(PUBLIC_KEY).unwrap().to_string(), ADDRESS);
+    }
+
+    #[test]
+    fn verifies_the_genesis_signature() {
+        // An open block hashes its source, representative and account, all the genesis key.
+        let genesis: Vec<u8> = hex::decode(GENESIS_KEY).unwrap();
+        let mut hasher = VarBlake2b::new(32).unwrap();
+
+        for _ in 0..3 {
+            hasher.input(&genesis[..]);
+        }
+
+        let mut hash = [0u8; 32];
+        hasher.variable_result(|result| hash.copy_from_slice(result));
+
+        assert_eq!(hex::encode_upper(&hash[..]), GENESIS_HASH);
+        assert!(verify(GENESIS_KEY, &hash, GENESIS_SIGNATURE));
+        assert!(!verify(PUBLIC_KEY, &hash, GENESIS_SIGNATURE));
+    }
+
+    #[test]
+    fn hashes_published_state_blocks() {
+        let published = StateBlock {
+            account: NanoAddress::parse(
+                "nano_3qgmh14nwztqw4wmcdzy4xpqeejey68chx6nciczwn9abji7ihhum9qtpmdr",
+            ).unwrap(),
+            previous: "F47B23107E5F34B2CE06F562B5C435DF72A533251CB414C51B2B62A8F63A00E4"
+                .to_string(),
+            representative: NanoAddress::parse(
+                "nano_1hza3f7wiiqa7ig3jczyxj5yo86yegcmqk3criaz838j91sxcckpfhbhhra1",
+            ).unwrap(),
+            balance: Amount::from_raw(1_000_000_000_000_000_000_000),
+            link: "19D3D919475DEED4696B5D13018151D1AF88B2BD3BCFF048B45031C1F36D1858".to_string(),
+        };
+
+        assert_eq!(hex::encode_upper(&published.hash().unwrap()[..]), STATE_HASH);
+    }
+
+    #[test]
+    fn signs_blocks_the_account_key_verifies() {
+        let account: NanoAddress = NanoAddress::parse(ADDRESS).unwrap();
+        let hash: [u8; 32] = block(account).hash().unwrap();
+        let signed: SignedBlock = block(account).sign(PRIVATE_KEY, "0".to_string()).unwrap();
+
+        assert!(verify(PUBLIC_KEY, &hash, &signed.signature));
+        assert!(!verify(GENESIS_KEY, &hash, &signed.signature));
+
+        let other = StateBlock {
+            balance: Amount::from_raw(1),
+            ..block(account)
+        };
+
+        assert_ne!(other.hash().unwrap(), hash);
+        assert!(!verify(PUBLIC_KEY, &other.hash().unwrap(), &signed.signature));
+    }
+
+    #[test]
+    fn refuses_keys_of_other_accounts() {
+        let genesis: NanoAddress = NanoAddress::from_public_key(GENESIS_KEY).unwrap();
+
+        match block(genesis).sign(PRIVATE_KEY, "0".to_string()) {
+            Err(SigningError::InvalidKey(_)) => (),
+            _ => panic!("signed a block of another account"),
+        }
+
+        let short_link = StateBlock {
+            link: "ABC".to_string(),
+            ..block(genesis)
+        };
+
+        match short_link.hash() {
+            Err(SigningError::InvalidField("link")) => (),
+            _ => panic!("hashed a short link"),
+        }
+    }
+}
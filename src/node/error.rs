@@ -0,0 +1,62 @@
+use std::error::Error;
+use std::fmt;
+
+/// Error reported by the node itself through the `error` field of an RPC response.
+#[derive(Debug, PartialEq)]
+pub enum NodeError {
+    InsufficientBalance,
+    BadAccount,
+    BadDestination,
+    BadAmount,
+    AccountNotFound,
+    WalletLocked,
+    WalletNotFound,
+    Other(String),
+}
+
+impl NodeError {
+    pub fn from_message(message: &str) -> NodeError {
+        match message.trim().to_lowercase().as_str() {
+            "insufficient balance" => NodeError::InsufficientBalance,
+            "bad account number" | "bad source account" | "account not found in wallet" => {
+                NodeError::BadAccount
+            }
+            "bad destination account" => NodeError::BadDestination,
+            "bad amount format" | "bad amount number" => NodeError::BadAmount,
+            "account not found" => NodeError::AccountNotFound,
+            "wallet is locked" | "wallet locked" => NodeError::WalletLocked,
+            "wallet not found" | "bad wallet number" => NodeError::WalletNotFound,
+            _ => NodeError::Other(message.to_string()),
+        }
+    }
+
+    pub fn user_message(&self) -> &str {
+        match *self {
+            NodeError::InsufficientBalance => "insufficient funds",
+            NodeError::BadAccount => "the sending account is not valid",
+            NodeError::BadDestination => "the destination address is not valid",
+            NodeError::BadAmount => "the amount is not valid",
+            NodeError::AccountNotFound => {
+                "the account has not been opened yet, deposit some NANO first"
+            }
+            NodeError::WalletLocked => "the wallet is locked",
+            NodeError::WalletNotFound => "the wallet could not be found",
+            NodeError::Other(_) => "the node rejected the request",
+        }
+    }
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NodeError::Other(ref message) => write!(f, "{}", message),
+            _ => write!(f, "{}", self.user_message()),
+        }
+    }
+}
+
+impl Error for NodeError {
+    fn description(&self) -> &str {
+        self.user_message()
+    }
+}
@@ -0,0 +1,70 @@
+mod address;
+mod amount;
+mod block;
+mod error;
+mod rpc;
+mod signer;
+
+pub use self::address::NanoAddress;
+pub use self::amount::{Amount, AmountInput};
+pub use self::error::NodeError;
+pub use self::rpc::{NanoRpcClient, RpcConfig, RpcError, RpcFuture};
+pub use self::signer::Signer;
+
+#[derive(Deserialize)]
+pub struct Account {
+    #[serde(default)]
+    pub id: i64,
+
+    pub account: String,
+    pub public: String,
+    pub private: String,
+    pub wallet: String,
+}
+
+#[derive(Deserialize)]
+pub struct Key {
+    pub account: String,
+    pub public: String,
+    pub private: String,
+}
+
+#[derive(Deserialize)]
+pub struct Balance {
+    pub balance: Amount,
+    pub pending: Amount,
+}
+
+#[derive(Deserialize)]
+pub struct AccountInfo {
+    pub frontier: String,
+    pub balance: Amount,
+    pub representative: NanoAddress,
+}
+
+#[derive(Deserialize)]
+pub struct Wallet {
+    pub wallet: String,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryEntry {
+    #[serde(rename = "type")]
+    pub entry_type: String,
+
+    pub account: String,
+
+    #[serde(default)]
+    pub amount: Amount,
+
+    pub hash: String,
+
+    #[serde(default)]
+    pub local_timestamp: String,
+}
+
+pub struct PendingBlock {
+    pub hash: String,
+    pub amount: Amount,
+    pub source: String,
+}
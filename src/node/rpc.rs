@@ -0,0 +1,509 @@
+use futures::future;
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Stream};
+use hyper::header::{ContentLength, ContentType};
+use hyper::{Chunk, Client, Method, Request, Uri};
+use node::block::{SignedBlock, SigningError};
+use node::{AccountInfo, Amount, Balance, HistoryEntry, Key, NodeError, PendingBlock, Wallet};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use tokio_core::reactor::{Core, Timeout};
+
+pub type RpcFuture<T> = Box<Future<Item = T, Error = RpcError> + Send>;
+
+pub struct RpcConfig {
+    pub url: String,
+    pub timeout: Duration,
+    pub keep_alive_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    InvalidUrl(String),
+    Unavailable(String),
+    Timeout,
+    Status(u16),
+    Node(NodeError),
+    InvalidResponse(String),
+    Signing(SigningError),
+}
+
+/// Handle to a background thread that owns the event loop and a pooled hyper client.
+#[derive(Clone)]
+pub struct NanoRpcClient {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+struct Job {
+    body: String,
+    reply: oneshot::Sender<Result<Chunk, RpcError>>,
+}
+
+#[derive(Serialize)]
+struct BasicCommand {
+    action: &'static str,
+}
+
+#[derive(Serialize)]
+struct AccountCommand {
+    action: &'static str,
+    account: String,
+}
+
+#[derive(Serialize)]
+struct AccountInfoCommand {
+    action: &'static str,
+    account: String,
+    representative: bool,
+}
+
+#[derive(Serialize)]
+struct HashCommand {
+    action: &'static str,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct ProcessCommand {
+    action: &'static str,
+    json_block: bool,
+    subtype: &'static str,
+    block: SignedBlock,
+}
+
+#[derive(Serialize)]
+struct HistoryCommand {
+    action: &'static str,
+    account: String,
+    count: u32,
+}
+
+#[derive(Serialize)]
+struct PendingCommand {
+    action: &'static str,
+    account: String,
+    count: u32,
+    source: bool,
+}
+
+#[derive(Serialize)]
+struct ReceiveCommand {
+    action: &'static str,
+    wallet: String,
+    account: String,
+    block: String,
+}
+
+#[derive(Serialize)]
+struct WalletCommand {
+    action: &'static str,
+    wallet: String,
+    key: String,
+}
+
+#[derive(Serialize)]
+struct SendCommand {
+    action: &'static str,
+    wallet: String,
+    source: String,
+    destination: String,
+    amount: Amount,
+}
+
+#[derive(Deserialize)]
+struct AccountResponse {
+    #[allow(dead_code)]
+    account: String,
+}
+
+#[derive(Deserialize)]
+struct WorkResponse {
+    work: String,
+}
+
+#[derive(Deserialize)]
+struct HashResponse {
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct HistoryResponse {
+    // Unopened accounts report an empty string instead of an empty list
+    #[serde(default)]
+    history: Value,
+}
+
+#[derive(Deserialize)]
+struct PendingResponse {
+    // An empty string when nothing is pending
+    #[serde(default)]
+    blocks: Value,
+}
+
+#[derive(Deserialize)]
+struct PendingSource {
+    amount: Amount,
+    source: String,
+}
+
+#[derive(Deserialize)]
+struct BlockResponse {
+    block: String,
+}
+
+impl Default for RpcConfig {
+    fn default() -> RpcConfig {
+        RpcConfig {
+            url: "http://127.0.0.1:7076".to_string(),
+            timeout: Duration::from_secs(10),
+            keep_alive_timeout: Duration::from_secs(90),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpcError::InvalidUrl(ref url) => write!(f, "Invalid node url: {}", url),
+            RpcError::Unavailable(ref e) => write!(f, "Node is unavailable: {}", e),
+            RpcError::Timeout => write!(f, "Node did not respond in time"),
+            RpcError::Status(code) => write!(f, "Node responded with HTTP status {}", code),
+            RpcError::Node(ref e) => write!(f, "Node returned an error: {}", e),
+            RpcError::InvalidResponse(ref e) => write!(f, "Could not read node response: {}", e),
+            RpcError::Signing(ref e) => write!(f, "Could not sign the block: {}", e),
+        }
+    }
+}
+
+impl RpcError {
+    pub fn user_message(&self) -> &str {
+        match *self {
+            RpcError::Node(ref e) => e.user_message(),
+            RpcError::Unavailable(_) | RpcError::Timeout | RpcError::Status(_) => {
+                "the Nano node is currently unavailable, please try again later"
+            }
+            RpcError::InvalidUrl(_) | RpcError::InvalidResponse(_) => {
+                "the Nano node returned an unexpected response"
+            }
+            RpcError::Signing(_) => "the transaction could not be signed",
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn description(&self) -> &str {
+        "nano node rpc error"
+    }
+}
+
+impl NanoRpcClient {
+    pub fn new(config: RpcConfig) -> Result<NanoRpcClient, RpcError> {
+        let uri: Uri = match config.url.parse() {
+            Ok(u) => u,
+            Err(_) => return Err(RpcError::InvalidUrl(config.url)),
+        };
+
+        let (jobs, queue) = mpsc::unbounded();
+
+        match thread::Builder::new()
+            .name("nano-rpc".to_string())
+            .spawn(move || run(uri, config, queue))
+        {
+            Ok(_) => Ok(NanoRpcClient { jobs }),
+            Err(e) => Err(RpcError::Unavailable(e.to_string())),
+        }
+    }
+
+    pub fn call<C, T>(&self, command: &C) -> RpcFuture<T>
+    where
+        C: Serialize,
+        T: DeserializeOwned + Send + 'static,
+    {
+        let body: String = match serde_json::to_string(command) {
+            Ok(b) => b,
+            Err(e) => return Box::new(future::err(RpcError::InvalidResponse(e.to_string()))),
+        };
+
+        let (reply, response) = oneshot::channel();
+
+        if self.jobs.unbounded_send(Job { body, reply }).is_err() {
+            return Box::new(future::err(RpcError::Unavailable(
+                "rpc worker has stopped".to_string(),
+            )));
+        }
+
+        Box::new(response.then(|result| match result {
+            Ok(Ok(chunk)) => parse_response(&chunk),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(RpcError::Unavailable(
+                "rpc worker dropped the request".to_string(),
+            )),
+        }))
+    }
+
+    pub fn create_new_key(&self) -> RpcFuture<Key> {
+        self.call(&BasicCommand {
+            action: "key_create",
+        })
+    }
+
+    pub fn create_new_wallet(&self) -> RpcFuture<Wallet> {
+        self.call(&BasicCommand {
+            action: "wallet_create",
+        })
+    }
+
+    pub fn get_balance(&self, account: &str) -> RpcFuture<Balance> {
+        self.call(&AccountCommand {
+            action: "account_balance",
+            account: account.to_string(),
+        })
+    }
+
+    /// Frontier, balance and representative of an opened account. Unopened accounts fail with
+    /// `NodeError::AccountNotFound`.
+    pub fn get_account_info(&self, account: &str) -> RpcFuture<AccountInfo> {
+        self.call(&AccountInfoCommand {
+            action: "account_info",
+            account: account.to_string(),
+            representative: true,
+        })
+    }
+
+    /// Proof of work for the block following `root`: the previous block, or the account public
+    /// key for an opening block.
+    pub fn generate_work(&self, root: &str) -> RpcFuture<String> {
+        Box::new(
+            self.call(&HashCommand {
+                action: "work_generate",
+                hash: root.to_string(),
+            }).map(|r: WorkResponse| r.work),
+        )
+    }
+
+    /// Publishes a block signed by the bot, returning its hash.
+    pub fn process(&self, subtype: &'static str, block: SignedBlock) -> RpcFuture<String> {
+        Box::new(
+            self.call(&ProcessCommand {
+                action: "process",
+                json_block: true,
+                subtype,
+                block,
+            }).map(|r: HashResponse| r.hash),
+        )
+    }
+
+    pub fn get_account_history(&self, account: &str, count: u32) -> RpcFuture<Vec<HistoryEntry>> {
+        Box::new(
+            self.call(&HistoryCommand {
+                action: "account_history",
+                account: account.to_string(),
+                count,
+            }).and_then(|r: HistoryResponse| parse_list("history", r.history)),
+        )
+    }
+
+    /// Blocks sent to `account` that it has not received yet.
+    pub fn get_pending(&self, account: &str, count: u32) -> RpcFuture<Vec<PendingBlock>> {
+        Box::new(
+            self.call(&PendingCommand {
+                action: "pending",
+                account: account.to_string(),
+                count,
+                source: true,
+            }).and_then(|r: PendingResponse| {
+                let blocks: BTreeMap<String, PendingSource> = parse_list("blocks", r.blocks)?;
+
+                Ok(blocks
+                    .into_iter()
+                    .map(|(hash, pending)| PendingBlock {
+                        hash,
+                        amount: pending.amount,
+                        source: pending.source,
+                    })
+                    .collect())
+            }),
+        )
+    }
+
+    /// Pockets the pending `block` into `account` with the node wallet, returning the hash of
+    /// the receive block.
+    pub fn receive(&self, wallet: &str, account: &str, block: &str) -> RpcFuture<String> {
+        Box::new(
+            self.call(&ReceiveCommand {
+                action: "receive",
+                wallet: wallet.to_string(),
+                account: account.to_string(),
+                block: block.to_string(),
+            }).map(|b: BlockResponse| b.block),
+        )
+    }
+
+    pub fn add_key_to_wallet(&self, wallet: &str, key: &str) -> RpcFuture<()> {
+        Box::new(
+            self.call(&WalletCommand {
+                action: "wallet_add",
+                wallet: wallet.to_string(),
+                key: key.to_string(),
+            }).map(|_: AccountResponse| ()),
+        )
+    }
+
+    pub fn send(
+        &self,
+        from_wallet: &str,
+        from_account: &str,
+        to_account: &str,
+        amount: Amount,
+    ) -> RpcFuture<String> {
+        Box::new(
+            self.call(&SendCommand {
+                action: "send",
+                wallet: from_wallet.to_string(),
+                source: from_account.to_string(),
+                destination: to_account.to_string(),
+                amount,
+            }).map(|b: BlockResponse| b.block),
+        )
+    }
+}
+
+fn run(uri: Uri, config: RpcConfig, queue: mpsc::UnboundedReceiver<Job>) {
+    let mut core = match Core::new() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Could not start the nano rpc event loop: {}", e);
+            return;
+        }
+    };
+
+    let handle = core.handle();
+    let client = Client::configure()
+        .keep_alive(true)
+        .keep_alive_timeout(Some(config.keep_alive_timeout))
+        .build(&handle);
+
+    let worker = queue.for_each(|job| {
+        let mut req = Request::new(Method::Post, uri.clone());
+
+        req.headers_mut().set(ContentType::json());
+        req.headers_mut()
+            .set(ContentLength(job.body.len() as u64));
+        req.set_body(job.body);
+
+        let request = client
+            .request(req)
+            .map_err(|e| RpcError::Unavailable(e.to_string()))
+            .and_then(|res| {
+                let status = res.status();
+
+                res.body()
+                    .concat2()
+                    .map_err(|e| RpcError::Unavailable(e.to_string()))
+                    .and_then(move |body| {
+                        if status.is_success() {
+                            Ok(body)
+                        } else {
+                            Err(RpcError::Status(status.as_u16()))
+                        }
+                    })
+            });
+
+        let reply = job.reply;
+
+        match Timeout::new(config.timeout, &handle) {
+            Ok(timeout) => {
+                let timeout = timeout.then(|_| Err::<Chunk, RpcError>(RpcError::Timeout));
+
+                handle.spawn(
+                    request
+                        .select(timeout)
+                        .map(|(body, _)| body)
+                        .map_err(|(e, _)| e)
+                        .then(move |result| {
+                            let _ = reply.send(result);
+                            Ok(())
+                        }),
+                );
+            }
+            Err(e) => {
+                let _ = reply.send(Err(RpcError::Unavailable(e.to_string())));
+            }
+        }
+
+        Ok(())
+    });
+
+    let _ = core.run(worker);
+}
+
+fn parse_response<T: DeserializeOwned>(chunk: &Chunk) -> Result<T, RpcError> {
+    let value: Value = match serde_json::from_slice(chunk) {
+        Ok(v) => v,
+        Err(e) => return Err(RpcError::InvalidResponse(e.to_string())),
+    };
+
+    if let Some(error) = value.get("error").and_then(|e| e.as_str()) {
+        return Err(RpcError::Node(NodeError::from_message(error)));
+    }
+
+    match serde_json::from_value(value) {
+        Ok(r) => Ok(r),
+        Err(e) => Err(RpcError::InvalidResponse(e.to_string())),
+    }
+}
+
+/// Reads a list the node writes as an empty string, or leaves out, when there is nothing in it.
+fn parse_list<T: DeserializeOwned + Default>(field: &str, value: Value) -> Result<T, RpcError> {
+    match value {
+        Value::Null => Ok(T::default()),
+        Value::String(ref s) if s.is_empty() => Ok(T::default()),
+        value => match serde_json::from_value(value) {
+            Ok(list) => Ok(list),
+            Err(e) => Err(RpcError::InvalidResponse(format!("{}: {}", field, e))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(blocks: Value) -> Result<BTreeMap<String, PendingSource>, RpcError> {
+        parse_list("blocks", blocks)
+    }
+
+    #[test]
+    fn reads_empty_lists_the_way_the_node_writes_them() {
+        for empty in &[json!(""), json!(null), json!({})] {
+            assert!(pending(empty.clone()).expect("empty blocks").is_empty());
+        }
+
+        let history: Vec<HistoryEntry> = parse_list("history", json!("")).expect("empty history");
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn refuses_lists_it_cannot_read() {
+        let blocks = json!({ "ABC": { "amount": "1", "source": "nano_1" } });
+
+        assert_eq!(pending(blocks).expect("pending blocks").len(), 1);
+
+        for invalid in &[
+            json!("nothing"),
+            json!({ "ABC": { "amount": "lots", "source": "nano_1" } }),
+            json!([1, 2]),
+        ] {
+            match pending(invalid.clone()) {
+                Err(RpcError::InvalidResponse(ref e)) => assert!(e.starts_with("blocks: ")),
+                _ => panic!("read {} as pending blocks", invalid),
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use config::NodeConfig;
+use db::KeyCipher;
+use futures::Future;
+use node::block::{self, SigningError, StateBlock, ZERO_HASH};
+use node::{
+    Account, AccountInfo, Amount, Key, NanoAddress, NanoRpcClient, NodeError, PendingBlock,
+    RpcError,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    /// Blocks of one account have to be published one after another, or two of them would
+    /// build on the same frontier and fork.
+    static ref CHAINS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// Creates keys and moves funds for bot accounts, either through the node wallet holding the key
+/// or, with `node.signing = "local"`, by building and signing state blocks itself.
+pub struct Signer<'a> {
+    node: &'a NanoRpcClient,
+    cipher: &'a KeyCipher,
+    config: &'a NodeConfig,
+}
+
+impl<'a> Signer<'a> {
+    pub fn new(
+        node: &'a NanoRpcClient,
+        cipher: &'a KeyCipher,
+        config: &'a NodeConfig,
+    ) -> Signer<'a> {
+        Signer {
+            node,
+            cipher,
+            config,
+        }
+    }
+
+    /// A fresh key with its plaintext private key, and the wallet holding it. Local keys are
+    /// generated here and belong to no wallet.
+    pub fn create_key(&self) -> Result<(Key, String), RpcError> {
+        if !self.config.local_signing() {
+            let wallet: String = self.node.create_new_wallet().wait()?.wallet;
+            let key: Key = self.node.create_new_key().wait()?;
+
+            self.node.add_key_to_wallet(&wallet, &key.private).wait()?;
+
+            return Ok((key, wallet));
+        }
+
+        let private: String = block::generate_private_key().map_err(RpcError::Signing)?;
+        let public: String = block::public_key(&private).map_err(RpcError::Signing)?;
+        let account: NanoAddress = match NanoAddress::from_public_key(&public) {
+            Ok(a) => a,
+            Err(e) => return Err(RpcError::Signing(SigningError::InvalidKey(e.to_string()))),
+        };
+
+        Ok((
+            Key {
+                account: account.to_string(),
+                public,
+                private,
+            },
+            String::new(),
+        ))
+    }
+
+    /// Sends `amount` from `account` to `destination`, returning the hash of the send block.
+    pub fn send(
+        &self,
+        account: &Account,
+        destination: &NanoAddress,
+        amount: Amount,
+    ) -> Result<String, RpcError> {
+        if !self.config.local_signing() {
+            return self
+                .node
+                .send(&account.wallet, &account.account, &destination.to_string(), amount)
+                .wait();
+        }
+
+        let chain = chain_lock(&account.account);
+        let _guard = chain.lock().expect("account chain lock");
+
+        let info: AccountInfo = match self.node.get_account_info(&account.account).wait() {
+            Ok(i) => i,
+            Err(RpcError::Node(NodeError::AccountNotFound)) => {
+                return Err(RpcError::Node(NodeError::InsufficientBalance))
+            }
+            Err(e) => return Err(e),
+        };
+
+        let balance: Amount = match info.balance.checked_sub(amount) {
+            Some(b) => b,
+            None => return Err(RpcError::Node(NodeError::InsufficientBalance)),
+        };
+
+        self.publish(
+            account,
+            "send",
+            info.frontier,
+            info.representative,
+            balance,
+            destination.public_key(),
+        )
+    }
+
+    /// Pockets a pending block into `account`, opening the account on its first receive.
+    pub fn receive(&self, account: &Account, pending: &PendingBlock) -> Result<String, RpcError> {
+        if !self.config.local_signing() {
+            return self
+                .node
+                .receive(&account.wallet, &account.account, &pending.hash)
+                .wait();
+        }
+
+        let chain = chain_lock(&account.account);
+        let _guard = chain.lock().expect("account chain lock");
+
+        let (subtype, previous, representative, balance) =
+            match self.node.get_account_info(&account.account).wait() {
+                Ok(i) => ("receive", i.frontier, i.representative, i.balance),
+                Err(RpcError::Node(NodeError::AccountNotFound)) => (
+                    "open",
+                    ZERO_HASH.to_string(),
+                    self.representative()?,
+                    Amount::default(),
+                ),
+                Err(e) => return Err(e),
+            };
+
+        let balance: Amount = match balance.checked_add(pending.amount) {
+            Some(b) => b,
+            None => {
+                return Err(RpcError::InvalidResponse(
+                    "the received balance overflows".to_string(),
+                ))
+            }
+        };
+
+        self.publish(
+            account,
+            subtype,
+            previous,
+            representative,
+            balance,
+            pending.hash.to_owned(),
+        )
+    }
+
+    fn publish(
+        &self,
+        account: &Account,
+        subtype: &'static str,
+        previous: String,
+        representative: NanoAddress,
+        balance: Amount,
+        link: String,
+    ) -> Result<String, RpcError> {
+        let address: NanoAddress = match NanoAddress::parse(&account.account) {
+            Ok(a) => a,
+            Err(_) => return Err(RpcError::Signing(SigningError::InvalidField("account"))),
+        };
+
+        // Opening blocks have no previous block, their work is done on the account key instead
+        let root: String = if previous == ZERO_HASH {
+            address.public_key()
+        } else {
+            previous.to_owned()
+        };
+
+        let unsigned = StateBlock {
+            account: address,
+            previous,
+            representative,
+            balance,
+            link,
+        };
+
+        let work: String = self.node.generate_work(&root).wait()?;
+
+        // The key is only unsealed once everything else is ready
+        let private: String = match self.cipher.private_key(account) {
+            Ok(p) => p,
+            Err(e) => return Err(RpcError::Signing(SigningError::InvalidKey(e.to_string()))),
+        };
+
+        let signed = unsigned.sign(&private, work).map_err(RpcError::Signing)?;
+
+        self.node.process(subtype, signed).wait()
+    }
+
+    /// Representative for newly opened accounts, checked by `Config::validate`.
+    fn representative(&self) -> Result<NanoAddress, RpcError> {
+        match self.config.representative {
+            Some(ref r) => NanoAddress::parse(r)
+                .map_err(|_| RpcError::Signing(SigningError::InvalidField("representative"))),
+            None => Err(RpcError::Signing(SigningError::InvalidField("representative"))),
+        }
+    }
+}
+
+fn chain_lock(account: &str) -> Arc<Mutex<()>> {
+    let mut chains = CHAINS.lock().expect("account chains lock");
+
+    Arc::clone(
+        chains
+            .entry(account.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(()))),
+    )
+}
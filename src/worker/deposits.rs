@@ -0,0 +1,422 @@
+use api::notify::{self, Notifier};
+use chrono::Utc;
+use config::NotificationConfig;
+use db;
+use futures::Future;
+use node::{Account, Amount, HistoryEntry, NanoRpcClient};
+use rusqlite::Connection;
+use serde_json;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{self, Message as WsMessage};
+use url::Url;
+
+/// Tells users about blocks received into their accounts. Confirmations arrive through the node
+/// websocket when one is configured; while it is down, and for accounts created after it
+/// subscribed, `account_history` is polled instead. Each block is announced once, whichever way
+/// it was seen first.
+pub struct DepositWatcher {
+    db_conn: Mutex<Connection>,
+    node: NanoRpcClient,
+    notifiers: Vec<Arc<Notifier>>,
+    websocket_url: Option<String>,
+    history_count: u32,
+    connected: AtomicBool,
+    subscribed: Mutex<HashSet<String>>,
+}
+
+pub struct Deposit {
+    pub account: String,
+    pub block: String,
+    pub amount: Amount,
+    pub source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Confirmation {
+    topic: String,
+    message: ConfirmationMessage,
+}
+
+#[derive(Deserialize)]
+struct ConfirmationMessage {
+    account: String,
+    amount: Amount,
+    hash: String,
+    block: ConfirmedBlock,
+}
+
+#[derive(Deserialize)]
+struct ConfirmedBlock {
+    #[serde(default)]
+    subtype: String,
+}
+
+impl DepositWatcher {
+    pub fn new(
+        db_conn: Mutex<Connection>,
+        node: NanoRpcClient,
+        notifiers: Vec<Arc<Notifier>>,
+        websocket_url: Option<String>,
+        config: &NotificationConfig,
+    ) -> DepositWatcher {
+        DepositWatcher {
+            db_conn,
+            node,
+            notifiers,
+            websocket_url,
+            history_count: config.history_count,
+            connected: AtomicBool::new(false),
+            subscribed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Starts the websocket listener, if any, and the polling fallback on their own threads.
+    /// Both retry every `interval`.
+    pub fn spawn(self, interval: Duration) -> Result<(), Box<Error>> {
+        let watcher = Arc::new(self);
+
+        if let Some(url) = watcher.websocket_url.to_owned() {
+            let listener = Arc::clone(&watcher);
+
+            thread::Builder::new()
+                .name("deposit-websocket".to_string())
+                .spawn(move || loop {
+                    if let Err(e) = listener.listen(&url) {
+                        println!("Node websocket {} failed: {}", url, e);
+                    }
+
+                    listener.connected.store(false, Ordering::SeqCst);
+                    thread::sleep(interval);
+                })?;
+        }
+
+        thread::Builder::new()
+            .name("deposit-poller".to_string())
+            .spawn(move || {
+                watcher.prime();
+
+                loop {
+                    thread::sleep(interval);
+                    watcher.poll();
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Announces `deposit` on every platform its owner has talked to the bot on, unless it was
+    /// announced before.
+    pub fn notify(&self, deposit: &Deposit) {
+        let timestamp: String = Utc::now().to_rfc3339();
+
+        match db::add_notification(&self.db_conn, &deposit.block, &deposit.account, &timestamp) {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(e) => {
+                println!("Could not record notification {}: {}", deposit.block, e);
+                return;
+            }
+        }
+
+        let references = match db::get_conversations(&self.db_conn, &deposit.account) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("Could not load conversations of {}: {}", deposit.account, e);
+                return;
+            }
+        };
+
+        for reference in &references {
+            let notifier = match self
+                .notifiers
+                .iter()
+                .find(|n| n.platform() == reference.platform)
+            {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let message = notify::deposit_message(
+                deposit.amount,
+                deposit.source.as_ref().map(|s| s.as_str()),
+                &deposit.block,
+            );
+
+            if let Err(e) = notifier.notify(reference, message) {
+                println!(
+                    "Could not notify {} on {} about {}: {}",
+                    reference.external_id, reference.platform, deposit.block, e
+                );
+            }
+        }
+    }
+
+    fn listen(&self, url: &str) -> Result<(), Box<Error>> {
+        let (mut socket, _) = tungstenite::connect(Url::parse(url)?)?;
+        let accounts: Vec<String> = self.accounts().into_iter().map(|a| a.account).collect();
+
+        // Accounts signed for locally are in no node wallet, so they are listed one by one
+        let subscribe = json!({
+            "action": "subscribe",
+            "topic": "confirmation",
+            "options": { "accounts": accounts },
+        });
+        socket.write_message(WsMessage::Text(subscribe.to_string()))?;
+
+        *self.subscribed.lock().expect("subscribed accounts lock") =
+            accounts.into_iter().collect();
+        self.connected.store(true, Ordering::SeqCst);
+        println!("Watching confirmations on {}", url);
+
+        // A close from the node comes back as an error, which ends this connection
+        loop {
+            let text: String = match socket.read_message()? {
+                WsMessage::Text(t) => t,
+                _ => continue,
+            };
+
+            let deposit: Deposit = match parse_deposit(&text) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            match db::is_bot_account(&self.db_conn, &deposit.account) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    println!("Could not look up {}: {}", deposit.account, e);
+                    continue;
+                }
+            }
+
+            self.notify(&deposit);
+        }
+    }
+
+    /// Checks the history of every account the websocket does not cover.
+    fn poll(&self) {
+        let covered: HashSet<String> = if self.connected.load(Ordering::SeqCst) {
+            self.subscribed
+                .lock()
+                .expect("subscribed accounts lock")
+                .clone()
+        } else {
+            HashSet::new()
+        };
+
+        for account in self.accounts() {
+            if covered.contains(&account.account) {
+                continue;
+            }
+
+            for deposit in self.received(&account) {
+                self.notify(&deposit);
+            }
+        }
+    }
+
+    /// Marks the history of accounts never watched before as announced, so turning notifications
+    /// on does not replay old deposits. Deposits missed while the bot was down are still sent.
+    fn prime(&self) {
+        let timestamp: String = Utc::now().to_rfc3339();
+
+        for account in self.accounts() {
+            match db::count_notifications(&self.db_conn, &account.account) {
+                Ok(0) => (),
+                Ok(_) => continue,
+                Err(e) => {
+                    println!("Could not count notifications of {}: {}", account.account, e);
+                    continue;
+                }
+            }
+
+            for deposit in self.received(&account) {
+                if let Err(e) = db::add_notification(
+                    &self.db_conn,
+                    &deposit.block,
+                    &deposit.account,
+                    &timestamp,
+                ) {
+                    println!("Could not record notification {}: {}", deposit.block, e);
+                }
+            }
+        }
+    }
+
+    fn accounts(&self) -> Vec<Account> {
+        match db::get_all_accounts(&self.db_conn) {
+            Ok(a) => a,
+            Err(e) => {
+                println!("Deposit watcher could not load accounts: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn received(&self, account: &Account) -> Vec<Deposit> {
+        let history: Vec<HistoryEntry> = match self
+            .node
+            .get_account_history(&account.account, self.history_count)
+            .wait()
+        {
+            Ok(h) => h,
+            Err(e) => {
+                println!("Could not fetch history of {}: {}", account.account, e);
+                return Vec::new();
+            }
+        };
+
+        history
+            .into_iter()
+            .filter(|entry| entry.entry_type == "receive")
+            .map(|entry| Deposit {
+                account: account.account.to_owned(),
+                block: entry.hash,
+                amount: entry.amount,
+                source: Some(entry.account),
+            })
+            .collect()
+    }
+}
+
+/// The deposit a websocket message confirms, if it confirms one. The first block received into
+/// an account opens it, so `open` blocks are deposits as much as `receive` blocks are.
+fn parse_deposit(text: &str) -> Option<Deposit> {
+    // Acknowledgements and other topics do not parse as confirmations
+    let confirmation: Confirmation = match serde_json::from_str(text) {
+        Ok(c) => c,
+        Err(_) => return None,
+    };
+
+    match (
+        confirmation.topic.as_str(),
+        confirmation.message.block.subtype.as_str(),
+    ) {
+        ("confirmation", "receive") | ("confirmation", "open") => Some(Deposit {
+            account: confirmation.message.account,
+            block: confirmation.message.hash,
+            amount: confirmation.message.amount,
+            source: None,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::notify::MockNotifier;
+    use db::ConversationReference;
+    use node::{Key, RpcConfig};
+
+    fn watcher(notifier: Arc<MockNotifier>) -> DepositWatcher {
+        let db_conn = db::get_connection(":memory:").expect("in-memory database");
+        let key = Key {
+            account: "nano_1owner".to_string(),
+            public: "public".to_string(),
+            private: "private".to_string(),
+        };
+
+        db::add_account(&db_conn, &key, "wallet", "teams", "29:owner").expect("account");
+        db::save_conversation(
+            &db_conn,
+            &ConversationReference {
+                platform: "teams".to_string(),
+                external_id: "29:owner".to_string(),
+                conversation: "a:personal".to_string(),
+                service_url: "https://smba.example/".to_string(),
+                bot_id: "28:bot".to_string(),
+                tenant_id: String::new(),
+            },
+            "2018-09-01T00:00:00+00:00",
+        ).expect("conversation");
+
+        DepositWatcher::new(
+            db_conn,
+            NanoRpcClient::new(RpcConfig::default()).expect("rpc client"),
+            vec![notifier as Arc<Notifier>],
+            None,
+            &NotificationConfig::default(),
+        )
+    }
+
+    fn deposit(block: &str) -> Deposit {
+        Deposit {
+            account: "nano_1owner".to_string(),
+            block: block.to_string(),
+            amount: Amount::from_raw(1_000_000_000_000_000_000_000_000_000_000),
+            source: Some("nano_1sender".to_string()),
+        }
+    }
+
+    #[test]
+    fn notifies_the_owner_once_per_block() {
+        let notifier = Arc::new(MockNotifier::new("teams"));
+        let watcher = watcher(Arc::clone(&notifier));
+
+        watcher.notify(&deposit("A"));
+        watcher.notify(&deposit("A"));
+        watcher.notify(&deposit("B"));
+
+        let sent: Vec<(String, String)> = notifier.sent();
+
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0, "29:owner");
+        assert_eq!(sent[0].1, "You received 1.000000 NANO");
+    }
+
+    #[test]
+    fn skips_platforms_without_a_notifier() {
+        let notifier = Arc::new(MockNotifier::new("hangouts"));
+        let watcher = watcher(Arc::clone(&notifier));
+
+        watcher.notify(&deposit("A"));
+
+        assert!(notifier.sent().is_empty());
+    }
+
+    fn confirmation(topic: &str, subtype: &str) -> String {
+        json!({
+            "topic": topic,
+            "time": "1564935350664",
+            "message": {
+                "account": "nano_1owner",
+                "amount": "1000000000000000000000000000000",
+                "hash": "3E746C3A6EA2FB0E3BDBC8FF7CC6F0FE9B2E5E27E6B9D28E36C5FA7F6C6B6A6A",
+                "confirmation_type": "active_quorum",
+                "block": {
+                    "type": "state",
+                    "account": "nano_1owner",
+                    "previous": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "balance": "1000000000000000000000000000000",
+                    "subtype": subtype,
+                },
+            },
+        }).to_string()
+    }
+
+    #[test]
+    fn counts_receive_and_open_blocks_as_deposits() {
+        for subtype in &["receive", "open"] {
+            let deposit: Deposit =
+                parse_deposit(&confirmation("confirmation", subtype)).expect(subtype);
+
+            assert_eq!(deposit.account, "nano_1owner");
+            assert_eq!(
+                deposit.block,
+                "3E746C3A6EA2FB0E3BDBC8FF7CC6F0FE9B2E5E27E6B9D28E36C5FA7F6C6B6A6A"
+            );
+            assert_eq!(deposit.amount.to_string(), "1.000000 NANO");
+        }
+
+        assert!(parse_deposit(&confirmation("confirmation", "send")).is_none());
+        assert!(parse_deposit(&confirmation("confirmation", "change")).is_none());
+        assert!(parse_deposit(&confirmation("votes", "open")).is_none());
+        assert!(parse_deposit(r#"{"ack": "subscribe", "time": "1564935350664"}"#).is_none());
+    }
+}
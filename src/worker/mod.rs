@@ -0,0 +1,5 @@
+mod deposits;
+mod receiver;
+
+pub use self::deposits::DepositWatcher;
+pub use self::receiver::Receiver;
@@ -0,0 +1,160 @@
+use chrono::Utc;
+use config::{NodeConfig, ReceiverConfig};
+use custody;
+use db::{self, KeyCipher};
+use futures::Future;
+use node::{Account, NanoRpcClient, PendingBlock, Signer};
+use rusqlite::Connection;
+use std::error::Error;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Background worker that pockets pending blocks for every bot account, so deposits become
+/// spendable without relying on the node wallet to auto-receive. With local signing it builds
+/// the receive blocks itself. With custody, funds received into user accounts are then swept
+/// into the hot wallet and credited in the ledger.
+pub struct Receiver {
+    db_conn: Mutex<Connection>,
+    node: NanoRpcClient,
+    cipher: KeyCipher,
+    node_config: NodeConfig,
+    batch: u32,
+    custodial: bool,
+}
+
+impl Receiver {
+    pub fn new(
+        db_conn: Mutex<Connection>,
+        node: NanoRpcClient,
+        cipher: KeyCipher,
+        node_config: NodeConfig,
+        config: &ReceiverConfig,
+        custodial: bool,
+    ) -> Receiver {
+        Receiver {
+            db_conn,
+            node,
+            cipher,
+            node_config,
+            batch: config.batch,
+            custodial,
+        }
+    }
+
+    /// Runs a pass every `interval` on its own thread, for as long as the process lives.
+    pub fn spawn(self, interval: Duration) -> Result<(), Box<Error>> {
+        thread::Builder::new()
+            .name("receiver".to_string())
+            .spawn(move || loop {
+                self.run_once();
+                thread::sleep(interval);
+            })?;
+
+        Ok(())
+    }
+
+    /// Receives whatever is pending right now. Blocks that fail are stored in
+    /// `receive_failures` and retried on the next pass, as are sweeps that were not credited.
+    pub fn run_once(&self) {
+        let accounts: Vec<Account> = match db::get_all_accounts(&self.db_conn) {
+            Ok(a) => a,
+            Err(e) => {
+                println!("Receiver could not load accounts: {}", e);
+                return;
+            }
+        };
+
+        let hot: Option<Account> = if self.custodial {
+            match custody::hot_wallet(&self.db_conn) {
+                Ok(h) => Some(h),
+                Err(e) => {
+                    println!("Receiver could not load the hot wallet, not sweeping: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if self.custodial {
+            if let Err(e) = custody::credit_sweeps(&self.db_conn) {
+                println!("Could not retry uncredited sweeps: {}", e);
+            }
+        }
+
+        let mut failed: usize = 0;
+
+        for account in &accounts {
+            let pending: Vec<PendingBlock> =
+                match self.node.get_pending(&account.account, self.batch).wait() {
+                    Ok(p) => p,
+                    Err(e) => {
+                        println!("Could not fetch pending blocks for {}: {}", account.account, e);
+                        continue;
+                    }
+                };
+
+            for block in &pending {
+                match self.receive(account, block) {
+                    Ok(hash) => {
+                        println!(
+                            "Received {} into {} from {} ({})",
+                            block.amount, account.account, block.source, hash
+                        );
+
+                        if let Err(e) = db::remove_receive_failure(&self.db_conn, &block.hash) {
+                            println!("Could not clear receive failure {}: {}", block.hash, e);
+                        }
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        println!(
+                            "Could not receive {} of {} into {}: {}",
+                            block.hash, block.amount, account.account, e
+                        );
+
+                        if let Err(e) = db::add_receive_failure(
+                            &self.db_conn,
+                            &block.hash,
+                            &account.account,
+                            &block.amount.raw().to_string(),
+                            &e.to_string(),
+                            &Utc::now().to_rfc3339(),
+                        ) {
+                            println!("Could not record receive failure {}: {}", block.hash, e);
+                        }
+                    }
+                }
+            }
+
+            match hot {
+                Some(ref h) if h.id != account.id => self.sweep(account, h),
+                _ => (),
+            }
+        }
+
+        if failed > 0 {
+            println!(
+                "{} pending blocks could not be received, see `rusty_nanobot receive-failures`",
+                failed
+            );
+        }
+    }
+
+    fn receive(&self, account: &Account, block: &PendingBlock) -> Result<String, Box<Error>> {
+        let signer = Signer::new(&self.node, &self.cipher, &self.node_config);
+
+        Ok(signer.receive(account, block)?)
+    }
+
+    fn sweep(&self, account: &Account, hot: &Account) {
+        let signer = Signer::new(&self.node, &self.cipher, &self.node_config);
+
+        match custody::sweep(&self.db_conn, &self.node, &signer, account, hot) {
+            Ok(ref amount) if amount.is_zero() => (),
+            Ok(amount) => println!("Swept {} from {} into the hot wallet", amount, account.account),
+            Err(e) => println!("Could not sweep {}: {}", account.account, e),
+        }
+    }
+}